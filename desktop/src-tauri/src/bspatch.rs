@@ -0,0 +1,208 @@
+use bzip2::read::BzDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Applies patches in the BSDIFF40 format written by bsdiff 4 and compatible tools: a
+// 32-byte header, then bzip2-compressed control, diff and extra blocks. The old file and
+// each block are read as streams, so memory use doesn't grow with the size of the file.
+
+const MAGIC: &[u8] = b"BSDIFF40";
+const HEADER_SIZE: u64 = 32;
+const BUFFER_SIZE: usize = 64 * 1024;
+
+// Rebuild the new file into `output` from `old` and the patch at `patch_path`. Blocking.
+pub fn apply(old: &mut File, patch_path: &Path, output: &mut impl Write) -> io::Result<()> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    File::open(patch_path)?.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(invalid("not a BSDIFF40 patch"));
+    }
+    let (ctrl_len, diff_len, new_size) = (offtin(&header[8..16]), offtin(&header[16..24]), offtin(&header[24..32]));
+    if ctrl_len < 0 || diff_len < 0 || new_size < 0 {
+        return Err(invalid("corrupt patch header"));
+    }
+
+    let diff_start = HEADER_SIZE + ctrl_len as u64;
+    let extra_start = diff_start
+        .checked_add(diff_len as u64)
+        .ok_or_else(|| invalid("corrupt patch header"))?;
+    let mut ctrl = block(patch_path, HEADER_SIZE, ctrl_len as u64)?;
+    let mut diff = block(patch_path, diff_start, diff_len as u64)?;
+    let mut extra = block(patch_path, extra_start, u64::MAX)?;
+
+    let old_size = old.seek(SeekFrom::End(0))? as i64;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut old_buffer = vec![0u8; BUFFER_SIZE];
+    let mut new_pos: i64 = 0;
+    let mut old_pos: i64 = 0;
+
+    while new_pos < new_size {
+        // Add this many diff bytes to the old file, copy this many extra bytes, then move
+        // this far in the old file
+        let mut control = [0u8; 24];
+        ctrl.read_exact(&mut control)?;
+        let (add_len, copy_len, seek_len) = (offtin(&control[..8]), offtin(&control[8..16]), offtin(&control[16..]));
+        let fits = add_len >= 0
+            && copy_len >= 0
+            && new_pos
+                .checked_add(add_len)
+                .and_then(|pos| pos.checked_add(copy_len))
+                .is_some_and(|end| end <= new_size);
+        if !fits {
+            return Err(invalid("corrupt patch control block"));
+        }
+
+        let mut remaining = add_len;
+        while remaining > 0 {
+            let len = remaining.min(BUFFER_SIZE as i64) as usize;
+            diff.read_exact(&mut buffer[..len])?;
+            add_old_bytes(old, old_size, old_pos, &mut buffer[..len], &mut old_buffer)?;
+            output.write_all(&buffer[..len])?;
+            old_pos = old_pos.saturating_add(len as i64);
+            remaining -= len as i64;
+        }
+
+        let copied = io::copy(&mut (&mut extra).take(copy_len as u64), output)?;
+        if copied != copy_len as u64 {
+            return Err(invalid("patch extra block is truncated"));
+        }
+
+        new_pos += add_len + copy_len;
+        old_pos = old_pos
+            .checked_add(seek_len)
+            .ok_or_else(|| invalid("corrupt patch control block"))?;
+    }
+
+    Ok(())
+}
+
+// Add the old file's bytes at `old_pos` onto `data`. Positions outside the old file add
+// nothing, as in bsdiff itself.
+fn add_old_bytes(old: &mut File, old_size: i64, old_pos: i64, data: &mut [u8], old_buffer: &mut [u8]) -> io::Result<()> {
+    let start = old_pos.max(0);
+    let end = old_pos.saturating_add(data.len() as i64).min(old_size);
+    if start >= end {
+        return Ok(());
+    }
+
+    let overlap = &mut old_buffer[..(end - start) as usize];
+    old.seek(SeekFrom::Start(start as u64))?;
+    old.read_exact(overlap)?;
+
+    let offset = (start - old_pos) as usize;
+    for (byte, old_byte) in data[offset..].iter_mut().zip(overlap.iter()) {
+        *byte = byte.wrapping_add(*old_byte);
+    }
+    Ok(())
+}
+
+// One of the patch's compressed blocks, read through its own handle on the patch file
+fn block(patch_path: &Path, offset: u64, len: u64) -> io::Result<BzDecoder<io::Take<BufReader<File>>>> {
+    let mut file = File::open(patch_path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(BzDecoder::new(BufReader::new(file).take(len)))
+}
+
+// bsdiff's integer encoding: little-endian magnitude with the sign in the top bit
+fn offtin(bytes: &[u8]) -> i64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes);
+    let negative = value[7] & 0x80 != 0;
+    value[7] &= 0x7f;
+    let magnitude = i64::from_le_bytes(value);
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+
+    fn offtout(value: i64) -> [u8; 8] {
+        let mut bytes = value.unsigned_abs().to_le_bytes();
+        if value < 0 {
+            bytes[7] |= 0x80;
+        }
+        bytes
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // A patch from (add, copy, seek) controls with their diff and extra bytes
+    fn patch(controls: &[(i64, i64, i64)], diff: &[u8], extra: &[u8], new_size: i64) -> Vec<u8> {
+        let ctrl: Vec<u8> = controls
+            .iter()
+            .flat_map(|(add, copy, seek)| [offtout(*add), offtout(*copy), offtout(*seek)].concat())
+            .collect();
+        let (ctrl, diff, extra) = (compress(&ctrl), compress(diff), compress(extra));
+
+        let mut patch = MAGIC.to_vec();
+        patch.extend(offtout(ctrl.len() as i64));
+        patch.extend(offtout(diff.len() as i64));
+        patch.extend(offtout(new_size));
+        patch.extend(ctrl);
+        patch.extend(diff);
+        patch.extend(extra);
+        patch
+    }
+
+    fn apply_to(old: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+        let dir = tempfile::tempdir().unwrap();
+        let old_path = dir.path().join("old");
+        let patch_path = dir.path().join("patch");
+        std::fs::write(&old_path, old).unwrap();
+        std::fs::write(&patch_path, patch).unwrap();
+
+        let mut output = Vec::new();
+        apply(&mut File::open(&old_path).unwrap(), &patch_path, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn offtin_reads_signed_values() {
+        assert_eq!(offtin(&offtout(0)), 0);
+        assert_eq!(offtin(&offtout(1234567)), 1234567);
+        assert_eq!(offtin(&offtout(-42)), -42);
+    }
+
+    #[test]
+    fn rebuilds_the_new_file() {
+        let old = b"hello world";
+        // "hello" capitalised, " there " as new data, then "world" from the old file
+        let mut diff = vec![0u8; 5];
+        diff[0] = b'H'.wrapping_sub(b'h');
+        let controls = [(5, 7, 1), (5, 1, 0)];
+        let patch = patch(&controls, &[diff, vec![0; 5]].concat(), b" there !", 18);
+
+        assert_eq!(apply_to(old, &patch).unwrap(), b"Hello there world!");
+    }
+
+    #[test]
+    fn seeks_backwards_in_the_old_file() {
+        let old = b"abc";
+        let patch = patch(&[(3, 0, -3), (3, 0, 0)], &[0; 6], b"", 6);
+
+        assert_eq!(apply_to(old, &patch).unwrap(), b"abcabc");
+    }
+
+    #[test]
+    fn controls_past_the_new_size_are_rejected() {
+        let patch = patch(&[(10, 0, 0)], &[0; 10], b"", 4);
+
+        assert_eq!(apply_to(b"abcd", &patch).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
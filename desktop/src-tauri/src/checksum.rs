@@ -0,0 +1,68 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Checksum mismatch: expected {}, got {}", self.expected, self.actual)
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+pub fn to_hex(hasher: Sha256) -> String {
+    hex::encode(hasher.finalize())
+}
+
+// Checksums from the API may come in either case
+pub fn matches(expected: &str, actual: &str) -> bool {
+    expected.trim().eq_ignore_ascii_case(actual)
+}
+
+fn hash_reader(reader: &mut impl Read, hasher: &mut Sha256, mut limit: u64) -> std::io::Result<()> {
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    while limit > 0 {
+        let want = (buffer.len() as u64).min(limit) as usize;
+        let read = reader.read(&mut buffer[..want])?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        limit -= read as u64;
+    }
+    Ok(())
+}
+
+// Hash the first `len` bytes already on disk, so a resumed stream can keep hashing
+// from where the previous attempt stopped. Runs on a blocking thread.
+pub async fn hash_file_prefix(path: &Path, len: u64) -> std::io::Result<Sha256> {
+    let path: PathBuf = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        let mut file = File::open(path)?;
+        hash_reader(&mut file, &mut hasher, len)?;
+        Ok(hasher)
+    })
+    .await?
+}
+
+// For code that is already on a blocking thread
+pub fn hash_file_blocking(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    hash_reader(&mut File::open(path)?, &mut hasher, u64::MAX)?;
+    Ok(to_hex(hasher))
+}
+
+pub async fn hash_file(path: &Path) -> std::io::Result<String> {
+    hash_file_prefix(path, u64::MAX).await.map(to_hex)
+}
@@ -0,0 +1,434 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+use crate::checksum::{self, ChecksumMismatch};
+use crate::extract::{self, ExtractProgress};
+use crate::interrupt::check_cancelled;
+use crate::manifest::{ManifestChunk, ManifestFile, ReleaseManifest, VerificationFailed};
+
+// Chunks waiting to be assembled into a game, stored by their SHA-256 under
+// <data_local_dir>/VAPR/Chunks/<first two hex digits>/<hash>. Survives pauses and
+// restarts, so an interrupted install only refetches the chunk it was in the middle of.
+// Once an install is assembled its chunks are released: the game's files are then the
+// only copy, and the next update seeds its unchanged chunks from them (plan_install)
+// rather than from the store, so nothing is fetched twice across versions.
+pub struct ChunkStore {
+    root: PathBuf,
+    leases: ChunkLeases,
+}
+
+// How many in-flight installs need each chunk, by hash. Shared by every install so one
+// finishing or cancelling never deletes a chunk another is still going to assemble from.
+#[derive(Clone, Default)]
+pub struct ChunkLeases(Arc<Mutex<HashMap<String, usize>>>);
+
+// One install's claim on the chunks of its manifest, from before it plans until it
+// releases them. Dropping it without release (a pause or failure) leaves the chunks
+// in the store for the resume.
+pub struct ChunkLease {
+    leases: ChunkLeases,
+    hashes: Vec<String>,
+}
+
+impl Drop for ChunkLease {
+    fn drop(&mut self) {
+        let mut held = self.leases.0.lock().unwrap_or_else(|e| e.into_inner());
+        give_back(&mut held, &self.hashes);
+    }
+}
+
+fn give_back(held: &mut HashMap<String, usize>, hashes: &[String]) {
+    for hash in hashes {
+        if let Some(count) = held.get_mut(hash) {
+            *count -= 1;
+            if *count == 0 {
+                held.remove(hash);
+            }
+        }
+    }
+}
+
+// What a chunked install still has to do: the files that don't match the manifest yet
+// and the chunks needed for them that nobody has locally
+pub struct ChunkPlan {
+    pub stale_files: Vec<ManifestFile>,
+    pub missing: Vec<ManifestChunk>,
+}
+
+impl ChunkStore {
+    pub fn open(leases: &ChunkLeases) -> Result<Self, String> {
+        let root = crate::get_vapr_data_directory()?.join("Chunks");
+        fs::create_dir_all(&root).map_err(|e| format!("Failed to create chunk store: {}", e))?;
+        Ok(Self {
+            root,
+            leases: leases.clone(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Hashes come from the manifest and end up in a path, so only accept real ones
+    fn chunk_path(&self, sha256: &str) -> Result<PathBuf, VerificationFailed> {
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(VerificationFailed(format!("invalid chunk hash in manifest: {}", sha256)));
+        }
+        let sha256 = sha256.to_ascii_lowercase();
+        Ok(self.root.join(&sha256[..2]).join(sha256))
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.chunk_path(sha256).is_ok_and(|path| path.exists())
+    }
+
+    // Store a chunk after checking it is what the manifest says. Written under a temp
+    // name first so a crash never leaves a truncated chunk behind under its real name.
+    pub fn insert(&self, chunk: &ManifestChunk, data: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let path = self.chunk_path(&chunk.sha256)?;
+
+        let actual = hex::encode(Sha256::digest(data));
+        if data.len() as u64 != chunk.size || !checksum::matches(&chunk.sha256, &actual) {
+            return Err(Box::new(ChecksumMismatch {
+                expected: chunk.sha256.clone(),
+                actual,
+            }));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("part");
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    // Claim every chunk of `manifest` for an install, so no other install removes them
+    pub fn lease(&self, manifest: &ReleaseManifest) -> ChunkLease {
+        let hashes: HashSet<String> = manifest
+            .files
+            .iter()
+            .flat_map(|file| &file.chunks)
+            .map(|chunk| chunk.sha256.to_ascii_lowercase())
+            .collect();
+
+        let mut held = self.leases.0.lock().unwrap_or_else(|e| e.into_inner());
+        for hash in &hashes {
+            *held.entry(hash.clone()).or_default() += 1;
+        }
+        ChunkLease {
+            leases: self.leases.clone(),
+            hashes: hashes.into_iter().collect(),
+        }
+    }
+
+    // End an install's lease and drop those of `chunks` that no other install holds
+    pub fn release(&self, mut lease: ChunkLease, chunks: &[ManifestChunk]) {
+        let mut held = self.leases.0.lock().unwrap_or_else(|e| e.into_inner());
+        give_back(&mut held, &std::mem::take(&mut lease.hashes));
+
+        for chunk in chunks {
+            if held.contains_key(&chunk.sha256.to_ascii_lowercase()) {
+                continue;
+            }
+            if let Ok(path) = self.chunk_path(&chunk.sha256) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+// Work out which files in `game_dir` are out of date and which of their chunks have to
+// be downloaded. Chunks that also appear in the current install, per the previous
+// manifest's layout or the new one's, are copied into the store instead of fetched.
+// Blocking; run it on spawn_blocking.
+pub fn plan_install(
+    store: &ChunkStore,
+    manifest: &ReleaseManifest,
+    previous: Option<&ReleaseManifest>,
+    game_dir: &Path,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<ChunkPlan, Box<dyn std::error::Error + Send + Sync>> {
+    let total_bytes = manifest.files.iter().map(|file| file.size).sum();
+    progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+    let mut stale_files = Vec::new();
+    for file in &manifest.files {
+        check_cancelled(cancel_token)?;
+
+        let path = game_dir.join(manifest_path(file)?);
+        let up_to_date = fs::metadata(&path).is_ok_and(|meta| meta.len() == file.size)
+            && checksum::hash_file_blocking(&path).is_ok_and(|actual| checksum::matches(&file.sha256, &actual));
+        if !up_to_date {
+            stale_files.push(file.clone());
+        }
+
+        progress.extracted_bytes.fetch_add(file.size, Ordering::Relaxed);
+    }
+
+    let mut needed: HashSet<String> = stale_files
+        .iter()
+        .flat_map(|file| &file.chunks)
+        .filter(|chunk| !store.contains(&chunk.sha256))
+        .map(|chunk| chunk.sha256.to_ascii_lowercase())
+        .collect();
+
+    let layouts = previous
+        .map(|previous| previous.files.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .chain(&stale_files);
+    for file in layouts {
+        if needed.is_empty() {
+            break;
+        }
+        check_cancelled(cancel_token)?;
+        let Some(relative) = extract::enclosed_path(Path::new(&file.path)) else {
+            continue;
+        };
+        seed_from_file(store, &game_dir.join(relative), &file.chunks, &mut needed);
+    }
+
+    let mut missing = Vec::new();
+    for chunk in stale_files.iter().flat_map(|file| &file.chunks) {
+        if needed.remove(&chunk.sha256.to_ascii_lowercase()) {
+            missing.push(chunk.clone());
+        }
+    }
+
+    Ok(ChunkPlan { stale_files, missing })
+}
+
+// Copy every needed chunk found in `path` into the store, assuming the file is laid out
+// as `chunks`. Whatever doesn't hash right is left for the download.
+fn seed_from_file(store: &ChunkStore, path: &Path, chunks: &[ManifestChunk], needed: &mut HashSet<String>) {
+    let Ok(mut file) = fs::File::open(path) else {
+        return;
+    };
+
+    let mut offset = 0;
+    for chunk in chunks {
+        let start = offset;
+        offset += chunk.size;
+
+        let key = chunk.sha256.to_ascii_lowercase();
+        if !needed.contains(&key) {
+            continue;
+        }
+
+        let mut data = vec![0u8; chunk.size as usize];
+        if file.seek(SeekFrom::Start(start)).is_err() || file.read_exact(&mut data).is_err() {
+            return;
+        }
+        if store.insert(chunk, &data).is_ok() {
+            needed.remove(&key);
+        }
+    }
+}
+
+// Rebuild each stale file from the store, then remove files the previous build had and
+// this one doesn't. Every file is written beside its old version and renamed over it.
+// Blocking; run it on spawn_blocking.
+pub fn assemble_files(
+    store: &ChunkStore,
+    manifest: &ReleaseManifest,
+    stale_files: &[ManifestFile],
+    previous: Option<&ReleaseManifest>,
+    game_dir: &Path,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let total_bytes = stale_files.iter().map(|file| file.size).sum();
+    progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+    for file in stale_files {
+        check_cancelled(cancel_token)?;
+
+        let path = game_dir.join(manifest_path(file)?);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut part_name = path.file_name().unwrap_or_default().to_os_string();
+        part_name.push(".vapr-part");
+        let part_path = path.with_file_name(part_name);
+
+        let written = write_chunks(store, &part_path, &file.chunks, cancel_token, progress);
+        if let Err(e) = written {
+            let _ = fs::remove_file(&part_path);
+            return Err(e);
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = file.mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&part_path, fs::Permissions::from_mode(mode))?;
+        }
+
+        fs::rename(&part_path, &path)?;
+    }
+
+    if let Some(previous) = previous {
+        let current: HashSet<&str> = manifest.files.iter().map(|file| file.path.as_str()).collect();
+        for file in previous.files.iter().filter(|file| !current.contains(file.path.as_str())) {
+            if let Some(relative) = extract::enclosed_path(Path::new(&file.path)) {
+                let _ = fs::remove_file(game_dir.join(relative));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_chunks(
+    store: &ChunkStore,
+    part_path: &Path,
+    chunks: &[ManifestChunk],
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut output = fs::File::create(part_path)?;
+    for chunk in chunks {
+        check_cancelled(cancel_token)?;
+
+        let mut input = fs::File::open(store.chunk_path(&chunk.sha256)?)?;
+        let copied = std::io::copy(&mut input, &mut output)?;
+        progress.extracted_bytes.fetch_add(copied, Ordering::Relaxed);
+    }
+    output.sync_all()?;
+    Ok(())
+}
+
+fn manifest_path(file: &ManifestFile) -> Result<PathBuf, VerificationFailed> {
+    extract::enclosed_path(Path::new(&file.path))
+        .ok_or_else(|| VerificationFailed(format!("invalid path in manifest: {}", file.path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest_with(chunk: &ManifestChunk) -> ReleaseManifest {
+        ReleaseManifest {
+            game_id: "game".to_string(),
+            version: "1.0.0".to_string(),
+            executable: "game.bin".to_string(),
+            archive_sha256: None,
+            chunk_base_url: Some("https://example.com/chunks".to_string()),
+            files: vec![ManifestFile {
+                path: "game.bin".to_string(),
+                size: chunk.size,
+                sha256: chunk.sha256.clone(),
+                mode: None,
+                chunks: vec![chunk.clone()],
+            }],
+        }
+    }
+
+    #[test]
+    fn released_chunks_stay_while_another_install_holds_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ChunkStore {
+            root: dir.path().to_path_buf(),
+            leases: ChunkLeases::default(),
+        };
+        let data = b"chunk data";
+        let chunk = ManifestChunk {
+            sha256: hex::encode(Sha256::digest(data)),
+            size: data.len() as u64,
+        };
+        let manifest = manifest_with(&chunk);
+        store.insert(&chunk, data).unwrap();
+
+        let first = store.lease(&manifest);
+        let second = store.lease(&manifest);
+        store.release(first, std::slice::from_ref(&chunk));
+        assert!(store.contains(&chunk.sha256));
+
+        store.release(second, std::slice::from_ref(&chunk));
+        assert!(!store.contains(&chunk.sha256));
+    }
+
+    #[test]
+    fn dropped_leases_keep_their_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ChunkStore {
+            root: dir.path().to_path_buf(),
+            leases: ChunkLeases::default(),
+        };
+        let data = b"chunk data";
+        let chunk = ManifestChunk {
+            sha256: hex::encode(Sha256::digest(data)),
+            size: data.len() as u64,
+        };
+        store.insert(&chunk, data).unwrap();
+
+        drop(store.lease(&manifest_with(&chunk)));
+        assert!(store.contains(&chunk.sha256));
+        assert!(store.leases.0.lock().unwrap().is_empty());
+    }
+
+    fn chunk_of(data: &[u8]) -> ManifestChunk {
+        ManifestChunk {
+            sha256: hex::encode(Sha256::digest(data)),
+            size: data.len() as u64,
+        }
+    }
+
+    fn chunked_manifest(version: &str, parts: &[&[u8]]) -> ReleaseManifest {
+        let contents = parts.concat();
+        ReleaseManifest {
+            game_id: "game".to_string(),
+            version: version.to_string(),
+            executable: "game.bin".to_string(),
+            archive_sha256: None,
+            chunk_base_url: Some("https://example.com/chunks".to_string()),
+            files: vec![ManifestFile {
+                path: "game.bin".to_string(),
+                size: contents.len() as u64,
+                sha256: hex::encode(Sha256::digest(&contents)),
+                mode: None,
+                chunks: parts.iter().map(|part| chunk_of(part)).collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn updates_reuse_chunks_from_the_installed_game_after_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        fs::create_dir(&game_dir).unwrap();
+        let store = ChunkStore {
+            root: dir.path().join("chunks"),
+            leases: ChunkLeases::default(),
+        };
+        let (cancel, progress) = (CancellationToken::new(), ExtractProgress::default());
+
+        // Install the first version and release its chunks, as a finished install does
+        let old = chunked_manifest("1.0.0", &[b"unchanged", b"old part"]);
+        let lease = store.lease(&old);
+        let plan = plan_install(&store, &old, None, &game_dir, &cancel, &progress).unwrap();
+        for chunk in &plan.missing {
+            let data: &[u8] = if chunk.size == 9 { b"unchanged" } else { b"old part" };
+            store.insert(chunk, data).unwrap();
+        }
+        assemble_files(&store, &old, &plan.stale_files, None, &game_dir, &cancel, &progress).unwrap();
+        store.release(lease, &old.files[0].chunks);
+        assert!(!store.contains(&chunk_of(b"unchanged").sha256));
+
+        // Only the changed chunk is left to download
+        let new = chunked_manifest("1.1.0", &[b"unchanged", b"new part"]);
+        let plan = plan_install(&store, &new, Some(&old), &game_dir, &cancel, &progress).unwrap();
+        assert_eq!(plan.missing.len(), 1);
+        assert_eq!(plan.missing[0].sha256, chunk_of(b"new part").sha256);
+        assert!(store.contains(&chunk_of(b"unchanged").sha256));
+    }
+}
@@ -0,0 +1,275 @@
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::chunks::ChunkLeases;
+use crate::extract::ExtractProgress;
+use crate::progress::{DownloadStatus, ThroughputMeter};
+use crate::scheduler::DownloadScheduler;
+use crate::throttle::RateLimiter;
+use crate::{http, integrity, library, retry, settings};
+use crate::{AppState, DownloadHost, DownloadState, EventSink};
+
+// Headless mode for QA automation and CI: `vapr-desktop <command> ...` runs one command
+// against the same games directory as the app. Stdout carries only JSON lines: download
+// events as they happen, then the command's result last. Diagnostics go to stderr as
+// plain text.
+const USAGE: &str = "\
+Usage: vapr-desktop <command> [arguments]
+
+Commands:
+  install <url> --game-id <id> [--name <name>] [--version <version>]
+          [--sha256 <hash>] [--mirror <url>]... [--library <folder>]
+  list
+  launch <game-id>
+  uninstall <game-id>
+  verify <game-id>
+  help";
+
+const COMMANDS: &[&str] = &["install", "list", "launch", "uninstall", "verify", "help"];
+
+// Run the command in `args` (without the program name) and return the exit code, or None
+// when the arguments aren't a CLI command and the app should start as usual
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.first().filter(|command| COMMANDS.contains(&command.as_str()))?;
+
+    if command == "help" {
+        println!("{}", USAGE);
+        return Some(0);
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => return Some(fail(&format!("Failed to start runtime: {}", e), None)),
+    };
+
+    let result = runtime.block_on(dispatch(command, &args[1..]));
+    Some(match result {
+        Ok(output) => {
+            println!("{}", output);
+            0
+        }
+        Err(CliError { message, kind }) => fail(&message, kind),
+    })
+}
+
+struct CliError {
+    message: String,
+    kind: Option<&'static str>,
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        Self { message, kind: None }
+    }
+}
+
+fn fail(message: &str, kind: Option<&'static str>) -> i32 {
+    let mut output = serde_json::json!({
+        "success": false,
+        "error": message
+    });
+    if let Some(kind) = kind {
+        output["kind"] = serde_json::json!(kind);
+    }
+    println!("{}", output);
+    1
+}
+
+async fn dispatch(command: &str, args: &[String]) -> Result<JsonValue, CliError> {
+    let args = ParsedArgs::parse(args)?;
+
+    match command {
+        "install" => install(&args).await,
+        "list" => {
+            args.expect_positional(0)?;
+            let games = crate::get_installed_games().await?;
+            Ok(serde_json::json!({ "success": true, "games": games }))
+        }
+        "launch" => launch(args.game_id()?).await,
+        "uninstall" => {
+            crate::uninstall_game(args.game_id()?.to_string()).await?;
+            Ok(serde_json::json!({ "success": true }))
+        }
+        "verify" => verify(args.game_id()?).await,
+        _ => Err(USAGE.to_string().into()),
+    }
+}
+
+// Positional arguments plus `--flag value` pairs; a flag may be given more than once
+struct ParsedArgs {
+    positional: Vec<String>,
+    flags: HashMap<String, Vec<String>>,
+}
+
+impl ParsedArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut flags: HashMap<String, Vec<String>> = HashMap::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(flag) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("--{} needs a value", flag))?;
+                    flags.entry(flag.to_string()).or_default().push(value.clone());
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        Ok(Self { positional, flags })
+    }
+
+    fn expect_positional(&self, count: usize) -> Result<(), String> {
+        if self.positional.len() != count {
+            return Err(USAGE.to_string());
+        }
+        Ok(())
+    }
+
+    fn game_id(&self) -> Result<&str, String> {
+        self.expect_positional(1)?;
+        Ok(&self.positional[0])
+    }
+
+    fn flag(&self, name: &str) -> Option<String> {
+        self.flags.get(name).and_then(|values| values.last().cloned())
+    }
+}
+
+// Download events as one JSON object per line, for whatever is driving the CLI
+struct StdoutEvents;
+
+impl EventSink for StdoutEvents {
+    fn emit_event(&self, event: &str, payload: JsonValue) {
+        println!("{}", serde_json::json!({ "event": event, "payload": payload }));
+    }
+}
+
+// App state for a single headless download: no journal, so the app's queue is left alone
+fn headless_state() -> Result<AppState, String> {
+    let settings = settings::load_settings();
+    let http_client = http::build_client(&settings)?;
+
+    Ok(AppState {
+        downloads: Arc::new(Mutex::new(HashMap::new())),
+        journal: None,
+        scheduler: Arc::new(Mutex::new(DownloadScheduler::new(settings.max_concurrent_downloads))),
+        scheduler_wakeup: Arc::new(Notify::new()),
+        global_rate_limiter: Arc::new(RateLimiter::new(settings.global_rate_limit)),
+        settings: Arc::new(RwLock::new(settings)),
+        http_client: Arc::new(RwLock::new(http_client)),
+        chunk_leases: ChunkLeases::default(),
+    })
+}
+
+async fn install(args: &ParsedArgs) -> Result<JsonValue, CliError> {
+    args.expect_positional(1)?;
+    let game_id = args
+        .flag("game-id")
+        .ok_or_else(|| "install needs --game-id".to_string())?;
+    let state = headless_state()?;
+    let library_root = library::resolve(
+        &*state.settings.read().await,
+        args.flag("library").as_deref().map(std::path::Path::new),
+    )?;
+
+    let download_state = DownloadState {
+        id: Uuid::new_v4().to_string(),
+        game_id: game_id.clone(),
+        game_name: args.flag("name").unwrap_or_else(|| game_id.clone()),
+        game_cover: None,
+        download_url: args.positional[0].clone(),
+        mirror_urls: args.flags.get("mirror").cloned().unwrap_or_default(),
+        version: args.flag("version"),
+        expected_sha256: args.flag("sha256"),
+        library_root,
+        rate_limiter: Arc::new(RateLimiter::new(0)),
+        is_paused: Arc::new(AtomicBool::new(false)),
+        cancel_token: CancellationToken::new(),
+        downloaded_bytes: Arc::new(Mutex::new(0)),
+        total_bytes: Arc::new(Mutex::new(0)),
+        status: Arc::new(Mutex::new(DownloadStatus::Downloading)),
+        throughput: Arc::new(Mutex::new(ThroughputMeter::default())),
+        schedule: Arc::new(Mutex::new(None)),
+    };
+
+    let host = DownloadHost {
+        events: Arc::new(StdoutEvents),
+        state,
+    };
+
+    // Ctrl-C cancels the download the same way the app's cancel button does
+    let cancel_token = download_state.cancel_token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel_token.cancel();
+        }
+    });
+
+    match crate::download_file_to_disk(host, download_state.clone()).await {
+        Ok((install_path, executable)) => Ok(serde_json::json!({
+            "success": true,
+            "install_path": install_path,
+            "executable": executable
+        })),
+        Err(e) => {
+            if download_state.cancel_token.is_cancelled() {
+                crate::discard_partial_download(&download_state.library_root, &download_state.game_name);
+            }
+            Err(CliError {
+                message: e.to_string(),
+                kind: Some(retry::classify(&*e).as_str()),
+            })
+        }
+    }
+}
+
+// Start the game and wait for it to exit, reporting the session the app would record
+async fn launch(game_id: &str) -> Result<JsonValue, CliError> {
+    let game_dir = crate::find_game_directory(game_id)?;
+    let content = fs::read_to_string(game_dir.join("vapr_game_info.json"))
+        .map_err(|e| format!("Failed to read game info: {}", e))?;
+    let game_info: JsonValue = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse game info: {}", e))?;
+    let executable = game_info["executable"]
+        .as_str()
+        .ok_or_else(|| "Game info has no executable".to_string())?;
+
+    let session = crate::start_game(executable)?;
+    let session = tokio::task::spawn_blocking(move || session.wait())
+        .await
+        .map_err(|e| format!("Failed to wait for game: {}", e))?;
+
+    Ok(serde_json::json!({ "success": true, "session": session }))
+}
+
+// Check the installed files against the record made when the game was installed
+async fn verify(game_id: &str) -> Result<JsonValue, CliError> {
+    let game_dir = crate::find_game_directory(game_id)?;
+    let record = integrity::load(&game_dir)
+        .ok_or_else(|| "No file record for this game; reinstall it to verify it".to_string())?;
+
+    let report = tokio::task::spawn_blocking(move || {
+        integrity::verify(&game_dir, &record, &CancellationToken::new(), &ExtractProgress::default())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| format!("Verification task failed: {}", e))??;
+
+    Ok(serde_json::json!({
+        "success": true,
+        "intact": report.is_intact(),
+        "missing": report.missing,
+        "modified": report.modified,
+        "extra": report.extra
+    }))
+}
@@ -0,0 +1,271 @@
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+use tokio_util::sync::CancellationToken;
+
+use crate::bspatch;
+use crate::checksum;
+use crate::extract::ExtractProgress;
+use crate::interrupt::check_cancelled;
+
+const PATCH_API_URL: &str = "https://vapr.club/api/games";
+
+// Manifest at the root of every patch archive
+const MANIFEST_NAME: &str = "patch.json";
+
+// Patched files are built here first, so a failed patch leaves the install untouched
+const STAGING_DIR_NAME: &str = ".vapr-patch";
+
+// What the API returns when it has a delta between two versions of a game
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchOffer {
+    pub url: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PatchManifest {
+    from_version: String,
+    to_version: String,
+    files: Vec<PatchEntry>,
+}
+
+// One change to the install. `data` names the archive entry holding the new file
+// (add) or the bsdiff patch against the old one (patch).
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum PatchEntry {
+    Add {
+        path: String,
+        data: String,
+        sha256: String,
+    },
+    Patch {
+        path: String,
+        data: String,
+        source_sha256: String,
+        sha256: String,
+    },
+    Delete {
+        path: String,
+    },
+}
+
+// The patch could not be applied to this install; the caller falls back to a full download
+#[derive(Debug)]
+pub struct PatchFailed(pub String);
+
+impl fmt::Display for PatchFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Patch failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for PatchFailed {}
+
+pub fn patch_path(temp_file_path: &Path) -> PathBuf {
+    temp_file_path.with_extension("patch")
+}
+
+// Left beside the download once a patch has failed, so resuming goes straight on with the
+// full archive instead of trying the same patch again
+pub fn failed_marker_path(temp_file_path: &Path) -> PathBuf {
+    temp_file_path.with_extension("patch-failed")
+}
+
+// Ask the API for a delta from `from_version` to `to_version`. A 404 means there is none.
+pub async fn find_patch(
+    client: &reqwest::Client,
+    game_id: &str,
+    from_version: &str,
+    to_version: &str,
+) -> Result<Option<PatchOffer>, Box<dyn std::error::Error + Send + Sync>> {
+    let response = client
+        .get(format!("{}/{}/patch", PATCH_API_URL, game_id))
+        .query(&[("from", from_version), ("to", to_version)])
+        .send()
+        .await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    Ok(Some(response.error_for_status()?.json::<PatchOffer>().await?))
+}
+
+// Only plain relative paths may be touched; anything else could escape the game directory
+fn install_path(game_dir: &Path, relative: &str) -> Result<PathBuf, PatchFailed> {
+    let relative = Path::new(relative);
+    if relative.as_os_str().is_empty()
+        || !relative.components().all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(PatchFailed(format!("Invalid path in patch: {}", relative.display())));
+    }
+    Ok(game_dir.join(relative))
+}
+
+// Stream an archive entry into `path`, returning its size
+fn extract_entry<R: Read + io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+    path: &Path,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|_| PatchFailed(format!("Missing entry {}", name)))?;
+    Ok(io::copy(&mut entry, &mut fs::File::create(path)?)?)
+}
+
+// Apply the patch archive at `patch_path` to the install in `game_dir`, one file at a
+// time. Every new file is built and verified in a staging directory before anything in
+// the install is replaced. Returns the paths it added or replaced, as the patch names
+// them. Blocking; run it on spawn_blocking.
+pub fn apply_patch(
+    patch_path: &Path,
+    game_dir: &Path,
+    from_version: &str,
+    to_version: &str,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(fs::File::open(patch_path)?))?;
+
+    let manifest_entry = archive
+        .by_name(MANIFEST_NAME)
+        .map_err(|_| PatchFailed(format!("Missing entry {}", MANIFEST_NAME)))?;
+    let manifest: PatchManifest = serde_json::from_reader(manifest_entry)
+        .map_err(|e| PatchFailed(format!("Invalid patch manifest: {}", e)))?;
+    if manifest.from_version != from_version || manifest.to_version != to_version {
+        return Err(Box::new(PatchFailed(format!(
+            "Patch is for {} -> {}, install needs {} -> {}",
+            manifest.from_version, manifest.to_version, from_version, to_version
+        ))));
+    }
+
+    let mut total_bytes = 0u64;
+    for entry in &manifest.files {
+        if let PatchEntry::Add { data, .. } | PatchEntry::Patch { data, .. } = entry {
+            total_bytes += archive.by_name(data).map(|e| e.size()).unwrap_or(0);
+        }
+    }
+    progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+    let staging_dir = game_dir.join(STAGING_DIR_NAME);
+    let _ = fs::remove_dir_all(&staging_dir);
+    fs::create_dir_all(&staging_dir)?;
+
+    let result = stage_files(&mut archive, &manifest, game_dir, &staging_dir, cancel_token, progress)
+        .and_then(|staged| commit_staged(&manifest, game_dir, staged));
+
+    let _ = fs::remove_dir_all(&staging_dir);
+    result?;
+
+    Ok(manifest
+        .files
+        .into_iter()
+        .filter_map(|entry| match entry {
+            PatchEntry::Add { path, .. } | PatchEntry::Patch { path, .. } => Some(path),
+            PatchEntry::Delete { .. } => None,
+        })
+        .collect())
+}
+
+// Build every added or patched file under `staging_dir`, checking each against its
+// expected hash. Returns (staged file, final path) pairs.
+fn stage_files<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    manifest: &PatchManifest,
+    game_dir: &Path,
+    staging_dir: &Path,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut staged = Vec::new();
+
+    for (i, entry) in manifest.files.iter().enumerate() {
+        check_cancelled(cancel_token)?;
+
+        let (path, data, expected) = match entry {
+            PatchEntry::Add { path, data, sha256 } => (path, data, sha256),
+            PatchEntry::Patch { path, data, sha256, .. } => (path, data, sha256),
+            PatchEntry::Delete { .. } => continue,
+        };
+
+        let target = install_path(game_dir, path)?;
+        let staged_path = staging_dir.join(i.to_string());
+
+        // Everything is streamed through files, so large game files never sit in memory
+        let entry_size = match entry {
+            PatchEntry::Patch { source_sha256, .. } => {
+                let source = checksum::hash_file_blocking(&target)
+                    .map_err(|e| PatchFailed(format!("Cannot read {}: {}", path, e)))?;
+                if !checksum::matches(source_sha256, &source) {
+                    return Err(Box::new(PatchFailed(format!(
+                        "{} does not match the version the patch was made for",
+                        path
+                    ))));
+                }
+
+                let diff_path = staging_dir.join(format!("{}.bsdiff", i));
+                let entry_size = extract_entry(archive, data, &diff_path)?;
+                let mut outfile = BufWriter::new(fs::File::create(&staged_path)?);
+                bspatch::apply(&mut fs::File::open(&target)?, &diff_path, &mut outfile)
+                    .map_err(|e| PatchFailed(format!("Cannot patch {}: {}", path, e)))?;
+                outfile.flush()?;
+                let _ = fs::remove_file(&diff_path);
+                entry_size
+            }
+            _ => extract_entry(archive, data, &staged_path)?,
+        };
+
+        let actual = checksum::hash_file_blocking(&staged_path)?;
+        if !checksum::matches(expected, &actual) {
+            return Err(Box::new(PatchFailed(format!("{} is corrupt after patching", path))));
+        }
+
+        progress.extracted_bytes.fetch_add(entry_size, Ordering::Relaxed);
+        staged.push((staged_path, target));
+    }
+
+    Ok(staged)
+}
+
+// Move staged files over the install and drop deleted ones
+fn commit_staged(
+    manifest: &PatchManifest,
+    game_dir: &Path,
+    staged: Vec<(PathBuf, PathBuf)>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for (staged_path, target) in staged {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        #[cfg(unix)]
+        {
+            // Keep the executable bit on patched binaries
+            if let Ok(metadata) = fs::metadata(&target) {
+                fs::set_permissions(&staged_path, metadata.permissions())?;
+            }
+        }
+
+        fs::rename(&staged_path, &target)?;
+    }
+
+    for entry in &manifest.files {
+        if let PatchEntry::Delete { path } = entry {
+            let target = install_path(game_dir, path)?;
+            if target.is_dir() {
+                fs::remove_dir_all(&target)?;
+            } else if target.exists() {
+                fs::remove_file(&target)?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::path::Path;
+
+// Not enough room on the target volume for a download and its extracted files
+#[derive(Debug, Clone, Copy)]
+pub struct InsufficientSpace {
+    pub required: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Not enough disk space: {} needed, {} available",
+            format_size(self.required),
+            format_size(self.available)
+        )
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+fn format_size(bytes: u64) -> String {
+    const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+    const MB: f64 = 1024.0 * 1024.0;
+
+    if bytes as f64 >= GB {
+        format!("{:.1} GB", bytes as f64 / GB)
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB)
+    }
+}
+
+// Free space on the volume holding `path`. The path itself may not exist yet (a game
+// directory before its first download), so ask about its nearest existing ancestor.
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(path);
+    fs2::available_space(existing)
+}
+
+// Bytes still to be written for an archive of `archive_size` with `downloaded` bytes
+// already on disk, plus its extracted contents. Until the archive itself can be
+// read the uncompressed size is unknown, so assume the archive doesn't compress at all.
+pub fn estimate_required(archive_size: u64, downloaded: u64, uncompressed_size: Option<u64>) -> u64 {
+    archive_size.saturating_sub(downloaded) + uncompressed_size.unwrap_or(archive_size)
+}
+
+// Total size of the regular files under `dir`
+pub fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+pub fn ensure_available(path: &Path, required: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let available = available_space(path)?;
+    if available < required {
+        return Err(Box::new(InsufficientSpace { required, available }));
+    }
+    Ok(())
+}
@@ -0,0 +1,525 @@
+use std::fmt;
+use std::fs;
+use std::io::{BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio_util::sync::CancellationToken;
+
+use crate::diskspace;
+use crate::interrupt::{check_cancelled, DownloadInterrupted};
+
+const COPY_BUFFER_SIZE: usize = 256 * 1024;
+
+// Bytes processed so far, read by the async side to report progress. Zip and 7z count
+// uncompressed bytes written; tarballs can't be sized without a full pass, so they count
+// compressed bytes read from the archive instead.
+#[derive(Debug, Default)]
+pub struct ExtractProgress {
+    pub extracted_bytes: AtomicU64,
+    pub total_bytes: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+    TarZst,
+    SevenZ,
+}
+
+// The downloaded file isn't an archive we know how to unpack
+#[derive(Debug)]
+pub struct UnsupportedArchive;
+
+impl fmt::Display for UnsupportedArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported archive format (expected zip, tar.gz, tar.zst or 7z)")
+    }
+}
+
+impl std::error::Error for UnsupportedArchive {}
+
+// Identify the archive by its leading magic bytes; download URLs and temp file names say
+// nothing reliable about the format
+pub fn detect_format(archive_path: &Path) -> std::io::Result<Option<ArchiveFormat>> {
+    let mut magic = [0u8; 6];
+    let mut file = fs::File::open(archive_path)?;
+    let mut read = 0;
+    while read < magic.len() {
+        match file.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    let magic = &magic[..read];
+
+    Ok(if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+        Some(ArchiveFormat::Zip)
+    } else if magic.starts_with(&[0x1f, 0x8b]) {
+        Some(ArchiveFormat::TarGz)
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(ArchiveFormat::TarZst)
+    } else if magic.starts_with(&[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c]) {
+        Some(ArchiveFormat::SevenZ)
+    } else {
+        None
+    })
+}
+
+// Unpack the archive at `archive_path` into `game_dir`, streaming each entry from disk so
+// memory use doesn't grow with the archive size. `game_dir` is a staging directory the
+// caller throws away if this fails. Blocking; run it on spawn_blocking.
+pub fn extract_archive(
+    archive_path: &Path,
+    game_dir: &Path,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let format = detect_format(archive_path)?.ok_or(UnsupportedArchive)?;
+    let mut extraction = Extraction::new(game_dir, cancel_token, progress);
+
+    match format {
+        ArchiveFormat::Zip => extract_zip(archive_path, &mut extraction),
+        ArchiveFormat::TarGz => extraction
+            .counting_reader(archive_path)
+            .map_err(Into::into)
+            .and_then(|reader| extract_tar(flate2::read::MultiGzDecoder::new(reader), &mut extraction)),
+        ArchiveFormat::TarZst => extraction
+            .counting_reader(archive_path)
+            .and_then(zstd::stream::read::Decoder::new)
+            .map_err(Into::into)
+            .and_then(|reader| extract_tar(reader, &mut extraction)),
+        ArchiveFormat::SevenZ => extract_7z(archive_path, &mut extraction),
+    }
+}
+
+fn extract_zip(
+    archive_path: &Path,
+    extraction: &mut Extraction,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let reader = BufReader::new(fs::File::open(archive_path)?);
+    let mut archive = zip::ZipArchive::new(reader)?;
+
+    let mut total_bytes = 0u64;
+    for i in 0..archive.len() {
+        total_bytes += archive.by_index_raw(i)?.size();
+    }
+    extraction.start(total_bytes)?;
+
+    for i in 0..archive.len() {
+        check_cancelled(extraction.cancel_token)?;
+
+        let mut file = archive.by_index(i)?;
+        let Some(relative) = file.enclosed_name().map(|path| path.to_path_buf()) else {
+            continue;
+        };
+
+        if file.name().ends_with('/') {
+            extraction.create_dir(&relative)?;
+        } else {
+            let mode = file.unix_mode();
+            extraction.write_file(&relative, &mut file, mode)?;
+        }
+    }
+
+    Ok(())
+}
+
+// A symlink or hard link from a tarball, created once every regular file is written so
+// no file is ever written through a link
+struct PendingLink {
+    relative: PathBuf,
+    target: PathBuf,
+    is_hard: bool,
+}
+
+// Tarballs keep symlinks and Unix permissions, which is why Linux builds ship as them
+fn extract_tar(
+    reader: impl Read,
+    extraction: &mut Extraction,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut links = Vec::new();
+
+    for entry in archive.entries()? {
+        check_cancelled(extraction.cancel_token)?;
+
+        let mut entry = entry?;
+        let Some(relative) = enclosed_path(&entry.path()?) else {
+            continue;
+        };
+        let entry_type = entry.header().entry_type();
+
+        if entry_type.is_dir() {
+            extraction.create_dir(&relative)?;
+        } else if entry_type.is_file() {
+            let mode = entry.header().mode().ok();
+            extraction.write_file(&relative, &mut entry, mode)?;
+        } else if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let Some(target) = entry.link_name()? else {
+                continue;
+            };
+            links.push(PendingLink {
+                relative,
+                target: target.to_path_buf(),
+                is_hard: entry_type.is_hard_link(),
+            });
+        }
+    }
+
+    for link in links {
+        check_cancelled(extraction.cancel_token)?;
+        extraction.create_link(&link)?;
+    }
+
+    Ok(())
+}
+
+// Where a symlink in `link_dir` (relative to the game directory) pointing at `target`
+// ends up, or None if it could point outside the game directory. Absolute targets are
+// never allowed, and `..` only at the start, since after a name it would climb out of
+// wherever that name leads rather than back to where the link is.
+fn resolve_link_target(link_dir: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved: Vec<&std::ffi::OsStr> = link_dir.iter().collect();
+    let mut named = false;
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                named = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if !named => {
+                resolved.pop()?;
+            }
+            _ => return None,
+        }
+    }
+    Some(resolved.iter().collect())
+}
+
+fn extract_7z(
+    archive_path: &Path,
+    extraction: &mut Extraction,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut archive = sevenz_rust::SevenZReader::open(archive_path, sevenz_rust::Password::empty())?;
+
+    let total_bytes = archive.archive().files.iter().map(|file| file.size()).sum();
+    extraction.start(total_bytes)?;
+
+    // The callback can only fail with the library's own error type, so ours are carried
+    // out here and the walk is stopped by returning false
+    let mut failure: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+    archive.for_each_entries(|entry, reader| {
+        let step = check_cancelled(extraction.cancel_token).and_then(|_| {
+            let Some(relative) = enclosed_path(Path::new(entry.name())) else {
+                return Ok(());
+            };
+            if entry.is_directory() {
+                Ok(extraction.create_dir(&relative)?)
+            } else {
+                extraction.write_file(&relative, reader, None)
+            }
+        });
+
+        match step {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                failure = Some(e);
+                Ok(false)
+            }
+        }
+    })?;
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Only plain relative paths may be extracted; anything else could escape the game directory
+pub fn enclosed_path(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => enclosed.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!enclosed.as_os_str().is_empty()).then_some(enclosed)
+}
+
+// Counts bytes read from the compressed archive into `extracted_bytes`
+struct CountingReader<'a, R> {
+    inner: R,
+    progress: &'a ExtractProgress,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.extracted_bytes.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+// One extraction into a game directory: writes entries and reports progress
+struct Extraction<'a> {
+    game_dir: &'a Path,
+    cancel_token: &'a CancellationToken,
+    progress: &'a ExtractProgress,
+    // Whether progress counts bytes written (zip, 7z) or bytes read (tarballs)
+    count_written: bool,
+}
+
+impl<'a> Extraction<'a> {
+    fn new(game_dir: &'a Path, cancel_token: &'a CancellationToken, progress: &'a ExtractProgress) -> Self {
+        Self {
+            game_dir,
+            cancel_token,
+            progress,
+            count_written: false,
+        }
+    }
+
+    // For formats that know their uncompressed size up front
+    fn start(&mut self, total_bytes: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.count_written = true;
+        self.progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+        // The pre-flight check could only guess the uncompressed size; now it's known exactly
+        diskspace::ensure_available(self.game_dir, total_bytes)?;
+        Ok(())
+    }
+
+    // For tarballs, whose progress is measured on the compressed input
+    fn counting_reader(&self, archive_path: &Path) -> std::io::Result<CountingReader<'a, BufReader<fs::File>>> {
+        let file = fs::File::open(archive_path)?;
+        self.progress.total_bytes.store(file.metadata()?.len(), Ordering::Relaxed);
+        Ok(CountingReader {
+            inner: BufReader::new(file),
+            progress: self.progress,
+        })
+    }
+
+    // Refuse to go through a symlink that leads out of the game directory, whether the
+    // archive made it or it came over from the live install. Only the deepest existing
+    // ancestor needs checking, since anything below it is created fresh.
+    fn check_inside(&self, dir: &Path) -> std::io::Result<()> {
+        let Some(existing) = dir.ancestors().find(|ancestor| ancestor.exists()) else {
+            return Ok(());
+        };
+        let game_dir = fs::canonicalize(self.game_dir)?;
+        if !fs::canonicalize(existing)?.starts_with(&game_dir) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Archive entry escapes the game directory through {}", existing.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    // Make way for the entry at `relative`. Files already there may be hard links into the
+    // live install, so they are unlinked rather than overwritten.
+    fn prepare_path(&self, relative: &Path) -> std::io::Result<()> {
+        let path = self.game_dir.join(relative);
+        if let Some(parent) = path.parent() {
+            self.check_inside(parent)?;
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    fn create_dir(&mut self, relative: &Path) -> std::io::Result<()> {
+        let path = self.game_dir.join(relative);
+        self.check_inside(&path)?;
+        fs::create_dir_all(path)
+    }
+
+    fn create_link(&mut self, link: &PendingLink) -> std::io::Result<()> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Link {} points outside the game directory", link.relative.display()),
+            )
+        };
+
+        self.prepare_path(&link.relative)?;
+        let path = self.game_dir.join(&link.relative);
+
+        // Hard link targets name another entry in the archive; symlink targets are
+        // relative to the directory the link really ended up in
+        let target = if link.is_hard {
+            enclosed_path(&link.target)
+        } else {
+            let game_dir = fs::canonicalize(self.game_dir)?;
+            let link_dir = fs::canonicalize(path.parent().unwrap_or(self.game_dir))?;
+            link_dir
+                .strip_prefix(&game_dir)
+                .ok()
+                .and_then(|link_dir| resolve_link_target(link_dir, &link.target))
+        }
+        .ok_or_else(invalid)?;
+        if link.is_hard {
+            let target = self.game_dir.join(target);
+            self.check_inside(&target)?;
+            return fs::hard_link(target, path);
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&link.target, path)
+        }
+        // Creating symlinks needs extra privileges on Windows, so copy the file it points at
+        #[cfg(not(unix))]
+        {
+            let target = self.game_dir.join(target);
+            self.check_inside(&target)?;
+            if target.is_file() {
+                fs::copy(target, path)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn write_file(
+        &mut self,
+        relative: &Path,
+        reader: &mut dyn Read,
+        mode: Option<u32>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.prepare_path(relative)?;
+        let outpath = self.game_dir.join(relative);
+
+        let mut outfile = fs::File::create(&outpath)?;
+        let counter = self.count_written.then_some(self.progress);
+        if !copy_until_cancelled(reader, &mut outfile, self.cancel_token, counter)? {
+            return Err(Box::new(DownloadInterrupted::Cancelled));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = mode {
+                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        Ok(())
+    }
+}
+
+// std::io::copy that gives up between buffers once `cancel_token` fires, counting the
+// bytes written into `progress` if given. Returns false if the copy was cut short.
+fn copy_until_cancelled(
+    reader: &mut dyn Read,
+    writer: &mut impl Write,
+    cancel_token: &CancellationToken,
+    progress: Option<&ExtractProgress>,
+) -> std::io::Result<bool> {
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        if cancel_token.is_cancelled() {
+            return Ok(false);
+        }
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        writer.write_all(&buffer[..read])?;
+        if let Some(progress) = progress {
+            progress.extracted_bytes.fetch_add(read as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_with(build: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        build(&mut builder);
+        builder.into_inner().unwrap()
+    }
+
+    fn append_symlink(builder: &mut tar::Builder<Vec<u8>>, path: &str, target: &str) {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_path(path).unwrap();
+        header.set_link_name(target).unwrap();
+        header.set_size(0);
+        header.set_cksum();
+        builder.append(&header, std::io::empty()).unwrap();
+    }
+
+    fn append_file(builder: &mut tar::Builder<Vec<u8>>, path: &str, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+
+    fn extract(archive: Vec<u8>, game_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let progress = ExtractProgress::default();
+        let cancel_token = CancellationToken::new();
+        let mut extraction = Extraction::new(game_dir, &cancel_token, &progress);
+        extract_tar(archive.as_slice(), &mut extraction)
+    }
+
+    #[test]
+    fn tar_files_are_not_written_through_symlinks() {
+        let outside = tempfile::tempdir().unwrap();
+        let game_dir = tempfile::tempdir().unwrap();
+        let archive = tar_with(|builder| {
+            append_symlink(builder, "x", outside.path().to_str().unwrap());
+            append_file(builder, "x/authorized_keys", b"key");
+        });
+
+        let _ = extract(archive, game_dir.path());
+
+        assert!(!outside.path().join("authorized_keys").exists());
+    }
+
+    #[test]
+    fn tar_symlinks_leaving_the_game_directory_are_rejected() {
+        for target in ["/etc", "../outside", "lib/../../outside"] {
+            let game_dir = tempfile::tempdir().unwrap();
+            let archive = tar_with(|builder| append_symlink(builder, "link", target));
+            assert!(extract(archive, game_dir.path()).is_err(), "{} was accepted", target);
+        }
+    }
+
+    #[test]
+    fn tar_symlinks_inside_the_game_directory_are_kept() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let archive = tar_with(|builder| {
+            append_file(builder, "lib/libgame.so.1", b"elf");
+            append_symlink(builder, "lib/libgame.so", "libgame.so.1");
+            append_symlink(builder, "bin/lib", "../lib");
+        });
+
+        extract(archive, game_dir.path()).unwrap();
+
+        assert_eq!(fs::read(game_dir.path().join("lib/libgame.so")).unwrap(), b"elf");
+        assert_eq!(fs::read(game_dir.path().join("bin/lib/libgame.so.1")).unwrap(), b"elf");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_existing_symlinks_out_of_the_game_directory_fail() {
+        let outside = tempfile::tempdir().unwrap();
+        let game_dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), game_dir.path().join("data")).unwrap();
+        let archive = tar_with(|builder| append_file(builder, "data/save.dat", b"x"));
+
+        assert!(extract(archive, game_dir.path()).is_err());
+        assert!(!outside.path().join("save.dat").exists());
+    }
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::time::Duration;
+
+use crate::settings::DownloadSettings;
+
+pub const USER_AGENT: &str = concat!("VAPR-Desktop/", env!("CARGO_PKG_VERSION"));
+
+// The client every outgoing request goes through, set up from the user's network
+// settings. Timeouts of 0 mean none.
+pub fn build_client(settings: &DownloadSettings) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder().user_agent(USER_AGENT);
+
+    if settings.connect_timeout_secs > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(settings.connect_timeout_secs));
+    }
+    // A read timeout rather than a total one, since a whole game can take hours
+    if settings.read_timeout_secs > 0 {
+        builder = builder.read_timeout(Duration::from_secs(settings.read_timeout_secs));
+    }
+
+    if let Some(proxy_url) = settings.proxy_url.as_deref().filter(|url| !url.trim().is_empty()) {
+        let proxy = reqwest::Proxy::all(proxy_url.trim())
+            .map_err(|e| format!("Invalid proxy URL {}: {}", proxy_url, e))?;
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(ca_bundle_path) = &settings.ca_bundle_path {
+        let pem = fs::read(ca_bundle_path).map_err(|e| {
+            format!("Failed to read CA bundle {}: {}", ca_bundle_path.display(), e)
+        })?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+            format!("Invalid CA bundle {}: {}", ca_bundle_path.display(), e)
+        })?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to set up HTTP client: {}", e))
+}
@@ -0,0 +1,258 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use tokio_util::sync::CancellationToken;
+
+use crate::checksum;
+use crate::extract::{self, ExtractProgress};
+use crate::interrupt::check_cancelled;
+use crate::manifest::{self, ReleaseManifest};
+use crate::staging;
+
+// Every file an install put on disk with its size and hash, written when the install
+// finishes so verify_game can later tell what is missing, modified or doesn't belong
+pub const FILE_RECORD_NAME: &str = "vapr_files.json";
+
+// Files VAPR keeps in a game's directory for itself; they aren't part of the game
+const OWN_FILES: &[&str] = &["vapr_game_info.json", manifest::INSTALLED_MANIFEST_NAME, FILE_RECORD_NAME];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledFile {
+    // Relative to the game directory, with forward slashes
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub files: Vec<InstalledFile>,
+}
+
+// Outcome of checking an install against its record; paths as in the record
+#[derive(Debug, Default, Serialize)]
+pub struct IntegrityReport {
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+    pub extra: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty() && self.extra.is_empty()
+    }
+}
+
+// Hash every file in `game_dir`. Files the manifest lists at the same size take their
+// hash from it, since the install has just been verified against it. Blocking; run it
+// on spawn_blocking.
+pub fn record(
+    game_dir: &Path,
+    manifest: Option<&ReleaseManifest>,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<FileRecord, Box<dyn std::error::Error + Send + Sync>> {
+    let known: HashMap<&str, (u64, &str)> = manifest
+        .into_iter()
+        .flat_map(|manifest| &manifest.files)
+        .map(|file| (file.path.as_str(), (file.size, file.sha256.as_str())))
+        .collect();
+
+    let paths = game_files(game_dir)?;
+    let sizes = paths
+        .iter()
+        .map(|path| Ok(fs::metadata(game_dir.join(path))?.len()))
+        .collect::<io::Result<Vec<u64>>>()?;
+    progress.total_bytes.store(sizes.iter().sum(), Ordering::Relaxed);
+
+    let mut files = Vec::new();
+    for (path, size) in paths.into_iter().zip(sizes) {
+        check_cancelled(cancel_token)?;
+
+        let sha256 = match known.get(path.as_str()) {
+            Some((known_size, sha256)) if *known_size == size => sha256.to_ascii_lowercase(),
+            _ => checksum::hash_file_blocking(&game_dir.join(&path))?,
+        };
+        progress.extracted_bytes.fetch_add(size, Ordering::Relaxed);
+        files.push(InstalledFile { path, size, sha256 });
+    }
+
+    Ok(FileRecord { files })
+}
+
+// Store the record in `game_dir`. Blocking.
+pub fn save(record: &FileRecord, game_dir: &Path) -> io::Result<()> {
+    staging::replace_file(&game_dir.join(FILE_RECORD_NAME), serde_json::to_string(record)?.as_bytes())
+}
+
+// The game's file record. Games installed before records were kept fall back to the
+// release manifest they were installed from, when it lists files.
+pub fn load(game_dir: &Path) -> Option<FileRecord> {
+    if let Ok(content) = fs::read_to_string(game_dir.join(FILE_RECORD_NAME)) {
+        return serde_json::from_str(&content).ok();
+    }
+
+    let manifest = manifest::load_installed(game_dir).filter(|manifest| !manifest.files.is_empty())?;
+    Some(FileRecord {
+        files: manifest
+            .files
+            .into_iter()
+            .map(|file| InstalledFile {
+                path: file.path,
+                size: file.size,
+                sha256: file.sha256,
+            })
+            .collect(),
+    })
+}
+
+// Check `game_dir` against its record: recorded files that are gone or differ, and files
+// that were never installed. Blocking; run it on spawn_blocking.
+pub fn verify(
+    game_dir: &Path,
+    record: &FileRecord,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<IntegrityReport, Box<dyn std::error::Error + Send + Sync>> {
+    let total_bytes = record.files.iter().map(|file| file.size).sum();
+    progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+    let mut report = IntegrityReport::default();
+    for file in &record.files {
+        check_cancelled(cancel_token)?;
+
+        let Some(relative) = extract::enclosed_path(Path::new(&file.path)) else {
+            report.missing.push(file.path.clone());
+            continue;
+        };
+        let path = game_dir.join(relative);
+
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {
+                let intact = metadata.len() == file.size
+                    && checksum::matches(&file.sha256, &checksum::hash_file_blocking(&path)?);
+                if !intact {
+                    report.modified.push(file.path.clone());
+                }
+            }
+            _ => report.missing.push(file.path.clone()),
+        }
+        progress.extracted_bytes.fetch_add(file.size, Ordering::Relaxed);
+    }
+
+    let recorded: HashSet<&str> = record.files.iter().map(|file| file.path.as_str()).collect();
+    report.extra = game_files(game_dir)?
+        .into_iter()
+        .filter(|path| !recorded.contains(path.as_str()))
+        .collect();
+
+    Ok(report)
+}
+
+// Relative paths of the regular files in `game_dir`, leaving out VAPR's own
+fn game_files(game_dir: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    collect_files(game_dir, "", &mut files)?;
+    files.retain(|path| !OWN_FILES.contains(&path.as_str()));
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = format!("{}{}", prefix, name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", path), files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_with(files: &[(&str, &[u8])]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn record_of(game_dir: &Path) -> FileRecord {
+        record(game_dir, None, &CancellationToken::new(), &ExtractProgress::default()).unwrap()
+    }
+
+    fn verify_against(game_dir: &Path, record: &FileRecord) -> IntegrityReport {
+        verify(game_dir, record, &CancellationToken::new(), &ExtractProgress::default()).unwrap()
+    }
+
+    #[test]
+    fn record_lists_game_files_but_not_vapr_files() {
+        let game = game_with(&[("game.exe", b"binary"), ("data/level.pak", b"level"), ("vapr_game_info.json", b"{}")]);
+
+        let record = record_of(game.path());
+        let paths: Vec<&str> = record.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["data/level.pak", "game.exe"]);
+        assert_eq!(record.files[1].size, 6);
+        assert_eq!(record.files[1].sha256, checksum::hash_file_blocking(&game.path().join("game.exe")).unwrap());
+    }
+
+    #[test]
+    fn an_untouched_install_is_intact() {
+        let game = game_with(&[("game.exe", b"binary"), ("data/level.pak", b"level")]);
+        let record = record_of(game.path());
+
+        assert!(verify_against(game.path(), &record).is_intact());
+    }
+
+    #[test]
+    fn verify_reports_missing_modified_and_extra_files() {
+        let game = game_with(&[("game.exe", b"binary"), ("data/level.pak", b"level"), ("data/save.dat", b"save")]);
+        let record = record_of(game.path());
+
+        fs::remove_file(game.path().join("data/save.dat")).unwrap();
+        // Same size, different contents
+        fs::write(game.path().join("data/level.pak"), b"LEVEL").unwrap();
+        fs::write(game.path().join("cheat.dll"), b"cheat").unwrap();
+
+        let report = verify_against(game.path(), &record);
+        assert_eq!(report.missing, ["data/save.dat"]);
+        assert_eq!(report.modified, ["data/level.pak"]);
+        assert_eq!(report.extra, ["cheat.dll"]);
+    }
+
+    #[test]
+    fn record_paths_outside_the_game_count_as_missing() {
+        let game = game_with(&[("game.exe", b"binary")]);
+        let mut record = record_of(game.path());
+        record.files.push(InstalledFile {
+            path: "../outside".to_string(),
+            size: 0,
+            sha256: String::new(),
+        });
+
+        assert_eq!(verify_against(game.path(), &record).missing, ["../outside"]);
+    }
+
+    #[test]
+    fn saved_records_load_back() {
+        let game = game_with(&[("game.exe", b"binary")]);
+        save(&record_of(game.path()), game.path()).unwrap();
+
+        let loaded = load(game.path()).unwrap();
+        assert_eq!(loaded.files.len(), 1);
+        assert!(verify_against(game.path(), &loaded).is_intact());
+    }
+}
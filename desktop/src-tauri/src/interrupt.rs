@@ -0,0 +1,33 @@
+use std::fmt;
+use tokio_util::sync::CancellationToken;
+
+// A download task stopped because the user asked it to, not because something failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadInterrupted {
+    Paused,
+    Cancelled,
+}
+
+impl fmt::Display for DownloadInterrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadInterrupted::Paused => write!(f, "Download paused"),
+            DownloadInterrupted::Cancelled => write!(f, "Download cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadInterrupted {}
+
+// Which interruption, if any, an error from the download pipeline represents
+pub fn interruption(error: &(dyn std::error::Error + 'static)) -> Option<DownloadInterrupted> {
+    error.downcast_ref::<DownloadInterrupted>().copied()
+}
+
+// For blocking work that checks for a cancel between steps
+pub fn check_cancelled(cancel_token: &CancellationToken) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if cancel_token.is_cancelled() {
+        return Err(Box::new(DownloadInterrupted::Cancelled));
+    }
+    Ok(())
+}
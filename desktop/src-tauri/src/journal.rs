@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+use crate::schedule::DownloadSchedule;
+
+// On-disk record of a queued download, enough to rebuild its DownloadState after a restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub game_id: String,
+    pub game_name: String,
+    pub game_cover: Option<String>,
+    pub download_url: String,
+    #[serde(default)]
+    pub mirror_urls: Vec<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    #[serde(default)]
+    pub library_root: Option<PathBuf>,
+    #[serde(default)]
+    pub rate_limit: u64,
+    #[serde(default)]
+    pub schedule: Option<DownloadSchedule>,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub is_paused: bool,
+}
+
+// Keeps the download queue in <data_local_dir>/VAPR/downloads.json
+pub struct DownloadJournal {
+    path: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl DownloadJournal {
+    pub fn open() -> Result<Self, String> {
+        Ok(Self {
+            path: crate::get_vapr_data_directory()?.join("downloads.json"),
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    // Read the journal; a missing or unreadable file is treated as an empty queue
+    pub fn load(&self) -> Vec<JournalEntry> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(_) => return Vec::new(),
+        };
+
+        match serde_json::from_str::<Vec<JournalEntry>>(&content) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Ignoring corrupt download journal {:?}: {}", self.path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    // Replace the journal contents. Written to a temp file first so a crash mid-write
+    // never leaves a truncated journal behind.
+    pub async fn write(&self, entries: &[JournalEntry]) -> Result<(), String> {
+        let _guard = self.write_lock.lock().await;
+
+        let json = serde_json::to_string_pretty(entries)
+            .map_err(|e| format!("Failed to serialize download journal: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, json)
+            .await
+            .map_err(|e| format!("Failed to write download journal: {}", e))?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(|e| format!("Failed to replace download journal: {}", e))?;
+
+        Ok(())
+    }
+}
@@ -1,23 +1,65 @@
+mod bspatch;
+mod checksum;
+mod chunks;
+pub mod cli;
+mod delta;
+mod diskspace;
+mod extract;
+mod http;
+mod integrity;
+mod interrupt;
+mod journal;
+mod library;
+mod manifest;
+mod progress;
+mod relocate;
+mod scheduler;
+mod resume;
+mod retry;
+mod schedule;
+mod segmented;
+mod settings;
+mod source;
+mod staging;
+mod throttle;
+mod versions;
 mod websocket;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use std::fs;
-use std::io::{Cursor, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tauri::AppHandle;
 use tauri::{Emitter, Manager, State};
 use semver::Version;
+use sha2::{Digest, Sha256};
 use websocket::{UserInfo, WebSocketServer};
-use tokio::sync::{oneshot, Mutex, RwLock};
+use chunks::{ChunkLeases, ChunkStore};
+use diskspace::InsufficientSpace;
+use extract::ExtractProgress;
+use interrupt::DownloadInterrupted;
+use journal::{DownloadJournal, JournalEntry};
+use manifest::{ManifestChunk, ReleaseManifest, VerificationFailed};
+use progress::{DownloadStatus, ThroughputMeter};
+use resume::{ResumeInvalidated, ResumeValidator};
+use retry::RetryPolicy;
+use schedule::DownloadSchedule;
+use scheduler::DownloadScheduler;
+use segmented::SegmentPlan;
+use settings::DownloadSettings;
+use source::DownloadSource;
+use staging::StagedInstall;
+use throttle::{RateLimiter, Throttle};
+use tokio::sync::{oneshot, Mutex, Notify, RwLock};
 use uuid::Uuid;
 use tokio::time::{timeout, Duration};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
 
 // Bridge that lets Rust request data via the JS client and await the response
 pub struct SdkBridge {
@@ -88,6 +130,8 @@ struct DownloadProgress {
     percentage: f32,
     speed: f64,
     eta: u64,
+    // Bytes/sec cap currently applied to this download, if any
+    rate_limit: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -113,15 +157,332 @@ struct DownloadState {
     game_name: String,
     game_cover: Option<String>,
     download_url: String,
+    // Fallbacks for download_url, in the caller's order of preference
+    mirror_urls: Vec<String>,
     version: Option<String>,
+    expected_sha256: Option<String>,
+    // Library folder the game is installed into
+    library_root: PathBuf,
+    rate_limiter: Arc<RateLimiter>,
     is_paused: Arc<AtomicBool>,
+    // Fired once by cancel_download; stops the transfer and any extraction in progress
+    cancel_token: CancellationToken,
     downloaded_bytes: Arc<Mutex<u64>>,
     total_bytes: Arc<Mutex<u64>>,
-    start_time: Arc<Mutex<Option<std::time::Instant>>>,
+    status: Arc<Mutex<DownloadStatus>>,
+    throughput: Arc<Mutex<ThroughputMeter>>,
+    schedule: Arc<Mutex<Option<DownloadSchedule>>>,
 }
 
+impl DownloadState {
+    // Rebuild a download from the journal. Restored downloads come back paused, or scheduled
+    // if they were waiting on a schedule, and pick up from whatever the .download temp file
+    // already holds. Entries from before library folders existed go to `default_root`.
+    fn from_journal_entry(entry: JournalEntry, default_root: &Path) -> Self {
+        let library_root = entry.library_root.unwrap_or_else(|| default_root.to_path_buf());
+
+        // Segmented downloads preallocate the temp file, so their progress lives in the plan
+        let temp_path = download_temp_path(&library_root, &entry.game_name);
+        let downloaded = match SegmentPlan::load(&segmented::plan_path(&temp_path)) {
+            Some(plan) => Some(plan.downloaded()),
+            None => fs::metadata(&temp_path).ok().map(|metadata| metadata.len()),
+        }
+        .unwrap_or(entry.downloaded_bytes);
+
+        let status = if entry.schedule.is_some() && !entry.is_paused {
+            DownloadStatus::Scheduled
+        } else {
+            DownloadStatus::Paused
+        };
+
+        Self {
+            id: entry.id,
+            game_id: entry.game_id,
+            game_name: entry.game_name,
+            game_cover: entry.game_cover,
+            download_url: entry.download_url,
+            mirror_urls: entry.mirror_urls,
+            version: entry.version,
+            expected_sha256: entry.expected_sha256,
+            library_root,
+            rate_limiter: Arc::new(RateLimiter::new(entry.rate_limit)),
+            is_paused: Arc::new(AtomicBool::new(true)),
+            cancel_token: CancellationToken::new(),
+            downloaded_bytes: Arc::new(Mutex::new(downloaded)),
+            total_bytes: Arc::new(Mutex::new(entry.total_bytes)),
+            status: Arc::new(Mutex::new(status)),
+            throughput: Arc::new(Mutex::new(ThroughputMeter::default())),
+            schedule: Arc::new(Mutex::new(entry.schedule)),
+        }
+    }
+
+    async fn to_journal_entry(&self) -> JournalEntry {
+        JournalEntry {
+            id: self.id.clone(),
+            game_id: self.game_id.clone(),
+            game_name: self.game_name.clone(),
+            game_cover: self.game_cover.clone(),
+            download_url: self.download_url.clone(),
+            mirror_urls: self.mirror_urls.clone(),
+            version: self.version.clone(),
+            expected_sha256: self.expected_sha256.clone(),
+            library_root: Some(self.library_root.clone()),
+            rate_limit: self.rate_limiter.rate(),
+            schedule: self.schedule.lock().await.clone(),
+            downloaded_bytes: *self.downloaded_bytes.lock().await,
+            total_bytes: *self.total_bytes.lock().await,
+            // Scheduled downloads also hold is_paused, but only the user pausing counts here
+            is_paused: *self.status.lock().await == DownloadStatus::Paused,
+        }
+    }
+
+    // Every URL the archive can be fetched from, primary first
+    fn download_urls(&self) -> Vec<String> {
+        std::iter::once(self.download_url.clone())
+            .chain(self.mirror_urls.iter().cloned())
+            .collect()
+    }
+
+    // Move to `next` if the lifecycle allows it; returns whether it did
+    async fn transition(&self, next: DownloadStatus) -> bool {
+        let mut status = self.status.lock().await;
+        if !status.can_transition_to(next) {
+            eprintln!(
+                "Ignoring invalid status change for download {}: {:?} -> {:?}",
+                self.id, *status, next
+            );
+            return false;
+        }
+        *status = next;
+        true
+    }
+
+    // Update the byte count and feed the throughput meter behind speed/ETA
+    async fn record_downloaded(&self, downloaded: u64) {
+        *self.downloaded_bytes.lock().await = downloaded;
+        self.throughput.lock().await.record(downloaded);
+    }
+
+    // Same numbers for download-progress events and get_active_downloads
+    async fn progress(&self, rate_limit: Option<u64>) -> DownloadProgress {
+        let downloaded = *self.downloaded_bytes.lock().await;
+        let total = *self.total_bytes.lock().await;
+        let (speed, eta) = {
+            let meter = self.throughput.lock().await;
+            (meter.bytes_per_sec(), meter.eta(total.saturating_sub(downloaded)))
+        };
+
+        DownloadProgress {
+            download_id: self.id.clone(),
+            game_id: self.game_id.clone(),
+            game_name: self.game_name.clone(),
+            game_cover: self.game_cover.clone(),
+            downloaded,
+            total,
+            percentage: if total > 0 {
+                (downloaded as f32 / total as f32) * 100.0
+            } else {
+                0.0
+            },
+            speed: speed / 1024.0 / 1024.0, // Convert to MB/s
+            eta,
+            rate_limit,
+        }
+    }
+}
+
+#[derive(Clone)]
 struct AppState {
     downloads: Arc<Mutex<HashMap<String, DownloadState>>>,
+    // None when running headless, so the CLI never touches the app's download queue
+    journal: Option<Arc<DownloadJournal>>,
+    scheduler: Arc<Mutex<DownloadScheduler>>,
+    // Wakes the scheduler loop whenever the queue or a slot changes
+    scheduler_wakeup: Arc<Notify>,
+    settings: Arc<RwLock<DownloadSettings>>,
+    // Shared by every download, on top of each download's own limiter
+    global_rate_limiter: Arc<RateLimiter>,
+    // Rebuilt whenever the network settings change
+    http_client: Arc<RwLock<reqwest::Client>>,
+    // Chunks that running chunked installs still need
+    chunk_leases: ChunkLeases,
+}
+
+// Where download events go: the webview in the app, stderr in the CLI
+trait EventSink: Send + Sync {
+    fn emit_event(&self, event: &str, payload: JsonValue);
+}
+
+impl EventSink for tauri::AppHandle {
+    fn emit_event(&self, event: &str, payload: JsonValue) {
+        let _ = self.emit(event, payload);
+    }
+}
+
+// What the download pipeline runs against, so the same code serves the app and the CLI
+#[derive(Clone)]
+struct DownloadHost {
+    events: Arc<dyn EventSink>,
+    state: AppState,
+}
+
+impl DownloadHost {
+    fn from_app(app_handle: &tauri::AppHandle) -> Self {
+        Self {
+            events: Arc::new(app_handle.clone()),
+            state: app_handle.state::<AppState>().inner().clone(),
+        }
+    }
+
+    fn emit(&self, event: &str, payload: &impl Serialize) -> Result<(), serde_json::Error> {
+        self.events.emit_event(event, serde_json::to_value(payload)?);
+        Ok(())
+    }
+}
+
+impl EventSink for DownloadHost {
+    fn emit_event(&self, event: &str, payload: JsonValue) {
+        self.events.emit_event(event, payload);
+    }
+}
+
+// Limits a download's bytes count against: the global cap and its own
+fn download_throttle(state: &AppState, download: &DownloadState) -> Throttle {
+    Throttle::new(vec![
+        state.global_rate_limiter.clone(),
+        download.rate_limiter.clone(),
+    ])
+}
+
+// Move a download to `status` and announce it on the download-status channel.
+// `details` carries extra fields for that status (queue position, retry attempt...).
+// Returns false, without emitting, if the transition is not allowed.
+async fn set_download_status(
+    events: &dyn EventSink,
+    download_state: &DownloadState,
+    status: DownloadStatus,
+    message: &str,
+    details: JsonValue,
+) -> bool {
+    if !download_state.transition(status).await {
+        return false;
+    }
+
+    let mut payload = serde_json::json!({
+        "download_id": download_state.id.clone(),
+        "game_id": download_state.game_id.clone(),
+        "game_name": download_state.game_name.clone(),
+        "game_cover": download_state.game_cover.clone(),
+        "status": status.as_str(),
+        "message": message
+    });
+    if let (Some(payload), JsonValue::Object(details)) = (payload.as_object_mut(), details) {
+        payload.extend(details);
+    }
+
+    events.emit_event("download-status", payload);
+    true
+}
+
+// Hand a download to the scheduler, or hold it as scheduled while its schedule says it
+// may not run yet. Also stops a running download whose window has closed.
+async fn release_download(
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+    download: &DownloadState,
+    details: JsonValue,
+) {
+    let schedule = download.schedule.lock().await.clone();
+    let next_start = schedule.and_then(|schedule| schedule.next_start(chrono::Local::now()));
+
+    match next_start {
+        None => {
+            let status = *download.status.lock().await;
+            if !matches!(status, DownloadStatus::Downloading | DownloadStatus::Retrying) {
+                download.is_paused.store(false, Ordering::Relaxed);
+                state.scheduler.lock().await.enqueue(&download.id);
+                set_download_status(
+                    app_handle,
+                    download,
+                    DownloadStatus::Queued,
+                    "Waiting for a download slot...",
+                    details,
+                )
+                .await;
+            }
+        }
+        Some(next_start) => {
+            download.is_paused.store(true, Ordering::Relaxed);
+            {
+                let mut scheduler = state.scheduler.lock().await;
+                scheduler.track(&download.id);
+                scheduler.park(&download.id);
+            }
+
+            let mut details = details;
+            details["next_start"] = serde_json::json!(next_start.to_rfc3339());
+            set_download_status(
+                app_handle,
+                download,
+                DownloadStatus::Scheduled,
+                &format!("Scheduled to start at {}", next_start.format("%H:%M")),
+                details,
+            )
+            .await;
+        }
+    }
+
+    persist_downloads(state).await;
+    state.scheduler_wakeup.notify_one();
+}
+
+// Start scheduled downloads whose window has opened and hold back queued or running ones
+// whose window has closed
+async fn run_schedule_timer(app_handle: tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut ticker = tokio::time::interval(Duration::from_secs(30));
+
+    loop {
+        ticker.tick().await;
+
+        let downloads: Vec<DownloadState> = state.downloads.lock().await.values().cloned().collect();
+        for download in downloads {
+            let Some(schedule) = download.schedule.lock().await.clone() else {
+                continue;
+            };
+
+            let is_open = schedule.is_open(chrono::Local::now());
+            let due = match *download.status.lock().await {
+                DownloadStatus::Scheduled => is_open,
+                DownloadStatus::Queued | DownloadStatus::Downloading | DownloadStatus::Retrying => !is_open,
+                _ => false,
+            };
+
+            if due {
+                release_download(&app_handle, &state, &download, serde_json::json!({})).await;
+            }
+        }
+    }
+}
+
+// Write the current download queue to the journal so it survives a restart
+async fn persist_downloads(state: &AppState) {
+    let Some(journal) = &state.journal else {
+        return;
+    };
+
+    let order = state.scheduler.lock().await.order().to_vec();
+    let mut snapshot: Vec<DownloadState> = state.downloads.lock().await.values().cloned().collect();
+    snapshot.sort_by_key(|d| order.iter().position(|id| id == &d.id).unwrap_or(usize::MAX));
+
+    let mut entries = Vec::with_capacity(snapshot.len());
+    for download in &snapshot {
+        entries.push(download.to_journal_entry().await);
+    }
+
+    if let Err(e) = journal.write(&entries).await {
+        eprintln!("Failed to persist download journal: {}", e);
+    }
 }
 
 #[tauri::command]
@@ -130,10 +491,13 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-async fn check_version_compatibility() -> Result<VersionCheckResult, String> {
+async fn check_version_compatibility(state: State<'_, AppState>) -> Result<VersionCheckResult, String> {
     let current_version = env!("CARGO_PKG_VERSION");
 
-    let response = reqwest::get("https://vapr.club/api/desktop-version")
+    let client = state.http_client.read().await.clone();
+    let response = client
+        .get("https://vapr.club/api/desktop-version")
+        .send()
         .await
         .map_err(|e| format!("Failed to check version: {}", e))?;
 
@@ -166,11 +530,20 @@ async fn check_version_compatibility() -> Result<VersionCheckResult, String> {
     })
 }
 
-fn get_games_directory() -> Result<PathBuf, String> {
+fn get_vapr_data_directory() -> Result<PathBuf, String> {
     let base_dir = dirs::data_local_dir()
         .ok_or_else(|| "Failed to get local data directory".to_string())?;
 
-    let games_dir = base_dir.join("VAPR").join("Games");
+    let vapr_dir = base_dir.join("VAPR");
+
+    fs::create_dir_all(&vapr_dir)
+        .map_err(|e| format!("Failed to create VAPR directory: {}", e))?;
+
+    Ok(vapr_dir)
+}
+
+fn get_games_directory() -> Result<PathBuf, String> {
+    let games_dir = get_vapr_data_directory()?.join("Games");
 
     fs::create_dir_all(&games_dir)
         .map_err(|e| format!("Failed to create games directory: {}", e))?;
@@ -178,6 +551,67 @@ fn get_games_directory() -> Result<PathBuf, String> {
     Ok(games_dir)
 }
 
+fn safe_game_name(game_name: &str) -> String {
+    game_name.replace(" ", "_").replace(":", "")
+}
+
+// Partial archive for an in-flight download, kept inside the game's own directory
+fn download_temp_path(library_root: &Path, game_name: &str) -> PathBuf {
+    let safe_game_name = safe_game_name(game_name);
+    library_root
+        .join(&safe_game_name)
+        .join(format!("{}.download", safe_game_name))
+}
+
+// Where a download stands against the free space on its target volume
+enum SpaceCheck {
+    Fits,
+    // The archive fits but its extracted contents may not; the estimate is only a guess
+    // until the archive's central directory can be read
+    Tight(InsufficientSpace),
+    TooFull(InsufficientSpace),
+}
+
+// HEAD the download URL for its size and compare the space it needs with what's free.
+// Unknown sizes and failed probes are not treated as errors, since the server may simply
+// not say.
+async fn check_disk_space(
+    client: &reqwest::Client,
+    download_url: &str,
+    library_root: &Path,
+    game_name: &str,
+    mirror_directory: Option<&Path>,
+) -> Result<SpaceCheck, String> {
+    let temp_file_path = download_temp_path(library_root, game_name);
+
+    let source = source::source_for(download_url, client, mirror_directory)?;
+    let archive_size = match source.probe().await {
+        Ok(probe) => probe.total_size,
+        Err(e) => {
+            eprintln!("Size probe for {} failed, skipping space check: {}", game_name, e);
+            None
+        }
+    };
+    let Some(archive_size) = archive_size else {
+        return Ok(SpaceCheck::Fits);
+    };
+
+    let downloaded = fs::metadata(&temp_file_path).map(|m| m.len()).unwrap_or(0);
+    let available = diskspace::available_space(&temp_file_path)
+        .map_err(|e| format!("Failed to read free disk space: {}", e))?;
+
+    let archive_remaining = archive_size.saturating_sub(downloaded);
+    let required = diskspace::estimate_required(archive_size, downloaded, None);
+
+    Ok(if available < archive_remaining {
+        SpaceCheck::TooFull(InsufficientSpace { required: archive_remaining, available })
+    } else if available < required {
+        SpaceCheck::Tight(InsufficientSpace { required, available })
+    } else {
+        SpaceCheck::Fits
+    })
+}
+
 #[tauri::command]
 async fn start_download(
     app_handle: tauri::AppHandle,
@@ -188,18 +622,61 @@ async fn start_download(
     game_cover: Option<String>,
     download_url: String,
     version: Option<String>,
+    expected_sha256: Option<String>,
+    schedule: Option<DownloadSchedule>,
+    mirror_urls: Option<Vec<String>>,
+    library_root: Option<String>,
 ) -> Result<GameInstallResult, String> {
+    if let Some(schedule) = &schedule {
+        schedule.validate()?;
+    }
+
+    let (library_root, mirror_directory) = {
+        let settings = state.settings.read().await;
+        let library_root = library::resolve(&settings, library_root.as_deref().map(Path::new))?;
+        (library_root, settings.mirror_directory.clone())
+    };
+
+    // Refuse before anything is written if the archive itself can't fit
+    let client = state.http_client.read().await.clone();
+    let space_check = check_disk_space(
+        &client,
+        &download_url,
+        &library_root,
+        &game_name,
+        mirror_directory.as_deref(),
+    )
+    .await?;
+    if let SpaceCheck::TooFull(space) = space_check {
+        let _ = app_handle.emit("download-error", serde_json::json!({
+            "download_id": download_id,
+            "game_id": game_id,
+            "error": space.to_string(),
+            "kind": retry::ErrorKind::InsufficientSpace.as_str(),
+            "required_bytes": space.required,
+            "available_bytes": space.available
+        }));
+        return Err(space.to_string());
+    }
+
     let download_state = DownloadState {
         id: download_id.clone(),
         game_id: game_id.clone(),
         game_name: game_name.clone(),
         game_cover: game_cover.clone(),
         download_url: download_url.clone(),
+        mirror_urls: mirror_urls.unwrap_or_default(),
         version: version.clone(),
+        expected_sha256,
+        library_root,
+        rate_limiter: Arc::new(RateLimiter::new(0)),
         is_paused: Arc::new(AtomicBool::new(false)),
+        cancel_token: CancellationToken::new(),
         downloaded_bytes: Arc::new(Mutex::new(0)),
         total_bytes: Arc::new(Mutex::new(0)),
-        start_time: Arc::new(Mutex::new(Some(std::time::Instant::now()))),
+        status: Arc::new(Mutex::new(DownloadStatus::Queued)),
+        throughput: Arc::new(Mutex::new(ThroughputMeter::default())),
+        schedule: Arc::new(Mutex::new(schedule)),
     };
 
     {
@@ -207,19 +684,18 @@ async fn start_download(
         downloads.insert(download_id.clone(), download_state.clone());
     }
 
-    app_handle.emit("download-status", serde_json::json!({
-        "download_id": download_state.id.clone(),
-        "game_id": download_state.game_id.clone(),
-        "game_name": download_state.game_name.clone(),
-        "game_cover": download_state.game_cover.clone(),
-        "status": "starting",
-        "message": "Starting download..."
-    })).unwrap();
+    let warning = match space_check {
+        SpaceCheck::Tight(space) => serde_json::json!({
+            "warning": retry::ErrorKind::InsufficientSpace.as_str(),
+            "warning_message": format!("The extracted game may not fit. {}", space),
+            "required_bytes": space.required,
+            "available_bytes": space.available
+        }),
+        _ => serde_json::json!({}),
+    };
 
-    // Spawn download task
-    tauri::async_runtime::spawn(async move {
-        perform_download(app_handle, download_state).await;
-    });
+    // The scheduler loop starts it once a slot is free and its schedule allows
+    release_download(&app_handle, &state, &download_state, warning).await;
 
     Ok(GameInstallResult {
         success: true,
@@ -230,7 +706,44 @@ async fn start_download(
 }
 
 async fn perform_download(app_handle: tauri::AppHandle, download_state: DownloadState) {
-    let result = download_file_to_disk(app_handle.clone(), download_state.clone()).await;
+    let result = download_file_to_disk(DownloadHost::from_app(&app_handle), download_state.clone()).await;
+
+    let interrupted = result
+        .as_ref()
+        .err()
+        .and_then(|e| interrupt::interruption(&**e));
+    let leaves_queue = result.is_ok() || interrupted == Some(DownloadInterrupted::Cancelled);
+
+    // Finished and cancelled downloads leave the queue; failed or paused ones stay so
+    // they can be resumed
+    let state = app_handle.state::<AppState>();
+    {
+        let mut scheduler = state.scheduler.lock().await;
+        if leaves_queue {
+            scheduler.remove(&download_state.id);
+        } else {
+            scheduler.finish(&download_state.id);
+        }
+    }
+    if leaves_queue {
+        state.downloads.lock().await.remove(&download_state.id);
+    }
+    persist_downloads(&state).await;
+    state.scheduler_wakeup.notify_one();
+
+    match interrupted {
+        // pause_download already reported the new status
+        Some(DownloadInterrupted::Paused) => return,
+        Some(DownloadInterrupted::Cancelled) => {
+            discard_partial_download(&download_state.library_root, &download_state.game_name);
+            let _ = app_handle.emit("download-cancelled", serde_json::json!({
+                "download_id": download_state.id,
+                "game_id": download_state.game_id
+            }));
+            return;
+        }
+        None => {}
+    }
 
     match result {
         Ok((install_path, executable)) => {
@@ -242,11 +755,74 @@ async fn perform_download(app_handle: tauri::AppHandle, download_state: Download
             })).unwrap();
         }
         Err(e) => {
-            app_handle.emit("download-error", serde_json::json!({
+            download_state.transition(DownloadStatus::Failed).await;
+            let mut payload = serde_json::json!({
                 "download_id": download_state.id,
                 "game_id": download_state.game_id,
-                "error": e.to_string()
-            })).unwrap();
+                "error": e.to_string(),
+                "kind": retry::classify(&*e).as_str()
+            });
+            if let Some(space) = e.downcast_ref::<InsufficientSpace>() {
+                payload["required_bytes"] = serde_json::json!(space.required);
+                payload["available_bytes"] = serde_json::json!(space.available);
+            }
+            app_handle.emit("download-error", payload).unwrap();
+        }
+    }
+}
+
+// Starts queued downloads as slots free up and reports queue position changes.
+// Runs for the lifetime of the app, woken through AppState::scheduler_wakeup.
+async fn run_download_scheduler(app_handle: tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+
+    loop {
+        state.scheduler_wakeup.notified().await;
+
+        let (ready, position_changes) = {
+            let mut scheduler = state.scheduler.lock().await;
+            (scheduler.take_ready(), scheduler.position_changes())
+        };
+
+        let downloads = state.downloads.lock().await.clone();
+
+        for download_id in ready {
+            let Some(download) = downloads.get(&download_id).cloned() else {
+                state.scheduler.lock().await.finish(&download_id);
+                continue;
+            };
+
+            let started = set_download_status(
+                &app_handle,
+                &download,
+                DownloadStatus::Downloading,
+                "Starting download...",
+                serde_json::json!({}),
+            )
+            .await;
+            if !started {
+                state.scheduler.lock().await.finish(&download_id);
+                continue;
+            }
+            download.throughput.lock().await.reset();
+
+            let task_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                perform_download(task_handle, download).await;
+            });
+        }
+
+        for (download_id, queue_position) in position_changes {
+            if let Some(download) = downloads.get(&download_id) {
+                set_download_status(
+                    &app_handle,
+                    download,
+                    DownloadStatus::Queued,
+                    &format!("Queued (position {})", queue_position),
+                    serde_json::json!({ "queue_position": queue_position }),
+                )
+                .await;
+            }
         }
     }
 }
@@ -255,143 +831,1133 @@ async fn perform_download(app_handle: tauri::AppHandle, download_state: Download
 async fn get_active_downloads(
     state: State<'_, AppState>,
 ) -> Result<Vec<serde_json::Value>, String> {
-    let downloads = state.downloads.lock().await;
-    let active_downloads: Vec<serde_json::Value> = downloads
-        .values()
-        .map(|d| {
-            let downloaded = *d.downloaded_bytes.blocking_lock();
-            let total = *d.total_bytes.blocking_lock();
-            let percentage = if total > 0 {
-                (downloaded as f32 / total as f32) * 100.0
-            } else {
-                0.0
-            };
-
-            serde_json::json!({
-                "download_id": d.id,
-                "game_id": d.game_id,
-                "game_name": d.game_name,
-                "game_cover": d.game_cover,
-                "downloaded": downloaded,
-                "total": total,
-                "percentage": percentage,
-                "status": if d.is_paused.load(Ordering::Relaxed) { "paused" } else { "downloading" },
-                "speed": 0.0,
-                "eta": 0
-            })
-        })
-        .collect();
+    let order = state.scheduler.lock().await.order().to_vec();
+    let mut downloads: Vec<DownloadState> = state.downloads.lock().await.values().cloned().collect();
+    downloads.sort_by_key(|d| order.iter().position(|id| id == &d.id).unwrap_or(usize::MAX));
+
+    let mut active_downloads = Vec::with_capacity(downloads.len());
+    for d in &downloads {
+        let progress = d.progress(download_throttle(&state, d).effective_rate()).await;
+        let status = *d.status.lock().await;
+        let queue_position = state.scheduler.lock().await.queue_position(&d.id);
+        let schedule = d.schedule.lock().await.clone();
+        let next_start = schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_start(chrono::Local::now()))
+            .map(|next_start| next_start.to_rfc3339());
+
+        let mut entry = serde_json::to_value(&progress).map_err(|e| e.to_string())?;
+        if let Some(entry) = entry.as_object_mut() {
+            entry.insert("status".to_string(), serde_json::json!(status.as_str()));
+            entry.insert("queue_position".to_string(), serde_json::json!(queue_position));
+            entry.insert("schedule".to_string(), serde_json::json!(schedule));
+            entry.insert("next_start".to_string(), serde_json::json!(next_start));
+        }
+        active_downloads.push(entry);
+    }
 
     Ok(active_downloads)
 }
-// New improved download function that writes directly to disk
-async fn download_file_to_disk(
-    app_handle: tauri::AppHandle,
-    download_state: DownloadState,
-) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
-    let client = reqwest::Client::new();
 
-    // Setup paths
-    let vapr_games_dir = get_games_directory()?;
-    let safe_game_name = download_state.game_name.replace(" ", "_").replace(":", "");
-    let game_dir = vapr_games_dir.join(&safe_game_name);
-    fs::create_dir_all(&game_dir)?;
-
-    let temp_file_path = game_dir.join(format!("{}.download", safe_game_name));
+async fn emit_download_progress(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    throttle: &Throttle,
+) -> Result<(), serde_json::Error> {
+    let progress = download_state.progress(throttle.effective_rate()).await;
+    host.emit("download-progress", &progress)
+}
 
-    // Get already downloaded bytes if resuming
-    let start_byte = if temp_file_path.exists() {
-        tokio::fs::metadata(&temp_file_path).await?.len()
-    } else {
-        0
+// Stream the archive over one connection, appending to the temp file when resuming.
+// Returns the total archive size, plus the SHA-256 of the whole file when `hash` is set.
+async fn download_single_stream(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    source: &dyn DownloadSource,
+    temp_file_path: &Path,
+    throttle: &Throttle,
+    hash: bool,
+) -> Result<(u64, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let validator_path = resume::validator_path(temp_file_path);
+
+    // Get already downloaded bytes if resuming. A partial file is only trusted when we
+    // know which upstream version it came from; otherwise start over.
+    let if_range = ResumeValidator::load(&validator_path).and_then(|v| v.if_range());
+    let mut start_byte = match (&if_range, temp_file_path.exists()) {
+        (Some(_), true) => tokio::fs::metadata(temp_file_path).await?.len(),
+        _ => 0,
     };
 
-    *download_state.downloaded_bytes.lock().await = start_byte;
+    let body = source.fetch_from(start_byte, if_range.as_deref()).await?;
 
-    // Setup request with resume support
-    let mut request = client.get(&download_state.download_url);
-    if start_byte > 0 {
-        request = request.header("Range", format!("bytes={}-", start_byte));
+    // The source starts over when the partial file no longer matches it
+    start_byte = body.offset;
+    if start_byte == 0 {
+        body.validator.save(&validator_path)?;
     }
-
-    let response = request.send().await?;
-    let total_size = response.content_length().unwrap_or(0) + start_byte;
+    let total_size = body.total_size;
 
     *download_state.total_bytes.lock().await = total_size;
+    download_state.record_downloaded(start_byte).await;
+
+    // Hash while writing; a resumed download first catches up on the bytes already on disk
+    let mut hasher = match (hash, start_byte) {
+        (false, _) => None,
+        (true, 0) => Some(Sha256::new()),
+        (true, _) => Some(checksum::hash_file_prefix(temp_file_path, start_byte).await?),
+    };
 
     // Open file for writing (append if resuming)
     let mut file = if start_byte > 0 {
         tokio::fs::OpenOptions::new()
             .write(true)
             .append(true)
-            .open(&temp_file_path)
+            .open(temp_file_path)
             .await?
     } else {
-        File::create(&temp_file_path).await?
+        File::create(temp_file_path).await?
     };
 
     // Download with progress updates
-    let mut stream = response.bytes_stream();
+    let mut stream = body.stream;
     let mut downloaded = start_byte;
     let mut last_update = std::time::Instant::now();
+    let mut last_journal_write = std::time::Instant::now();
 
     use futures_util::StreamExt;
     while let Some(chunk) = stream.next().await {
         // Check if paused
         if download_state.is_paused.load(Ordering::Relaxed) {
             file.flush().await?;
-            return Err("Download paused".into());
+            return Err(Box::new(DownloadInterrupted::Paused));
         }
 
         let chunk = chunk?;
         file.write_all(&chunk).await?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&chunk);
+        }
         downloaded += chunk.len() as u64;
 
-        *download_state.downloaded_bytes.lock().await = downloaded;
+        download_state.record_downloaded(downloaded).await;
+        throttle.consume(chunk.len() as u64).await;
 
         // Update progress every 100ms to avoid overwhelming the UI
         if last_update.elapsed() > Duration::from_millis(100) {
             last_update = std::time::Instant::now();
 
-            let elapsed = download_state.start_time.lock().await
-                .as_ref()
-                .map(|t| t.elapsed().as_secs_f64())
-                .unwrap_or(1.0);
+            emit_download_progress(host, download_state, throttle).await?;
+        }
 
-            let speed = if elapsed > 0.0 {
-                (downloaded - start_byte) as f64 / elapsed
-            } else {
-                0.0
+        // Journal bytes done every few seconds rather than on every chunk
+        if last_journal_write.elapsed() > Duration::from_secs(5) {
+            last_journal_write = std::time::Instant::now();
+            persist_downloads(&host.state).await;
+        }
+    }
+
+    // Ensure all data is written
+    file.flush().await?;
+
+    if downloaded < total_size {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("Download ended early ({} of {} bytes)", downloaded, total_size),
+        )));
+    }
+
+    let _ = tokio::fs::remove_file(&validator_path).await;
+
+    Ok((total_size, hasher.map(checksum::to_hex)))
+}
+
+// Fetch the archive as parallel byte ranges into a preallocated temp file.
+// Progress per range is kept in a sidecar plan so the download can resume.
+async fn download_segmented(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    source: &dyn DownloadSource,
+    temp_file_path: &Path,
+    throttle: &Throttle,
+    plan: SegmentPlan,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let plan_path = segmented::plan_path(temp_file_path);
+    let total_size = plan.total_size;
+
+    if !temp_file_path.exists() {
+        let file = File::create(temp_file_path).await?;
+        file.set_len(total_size).await?;
+    }
+    plan.save(&plan_path)?;
+
+    let start_byte = plan.downloaded();
+    *download_state.total_bytes.lock().await = total_size;
+    download_state.record_downloaded(start_byte).await;
+
+    let plan = Arc::new(Mutex::new(plan));
+    let downloaded = Arc::new(AtomicU64::new(start_byte));
+
+    let workers = segmented::fetch_segments(
+        source,
+        temp_file_path,
+        plan.clone(),
+        downloaded.clone(),
+        download_state.is_paused.clone(),
+        throttle,
+    );
+    tokio::pin!(workers);
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
+    let mut last_journal_write = std::time::Instant::now();
+
+    let result = loop {
+        tokio::select! {
+            result = &mut workers => break result,
+            _ = ticker.tick() => {
+                download_state.record_downloaded(downloaded.load(Ordering::Relaxed)).await;
+                emit_download_progress(host, download_state, throttle).await?;
+
+                if last_journal_write.elapsed() > Duration::from_secs(5) {
+                    last_journal_write = std::time::Instant::now();
+                    plan.lock().await.save(&plan_path)?;
+                    persist_downloads(&host.state).await;
+                }
+            }
+        }
+    };
+
+    // Record how far each range got, whether the workers finished, paused or failed
+    plan.lock().await.save(&plan_path)?;
+    result?;
+
+    let _ = tokio::fs::remove_file(&plan_path).await;
+    download_state.record_downloaded(total_size).await;
+
+    Ok(total_size)
+}
+
+// Remove the temp archive and its sidecars, plus the game directory if nothing else is in it
+fn discard_partial_download(library_root: &Path, game_name: &str) {
+    let temp_file = download_temp_path(library_root, game_name);
+
+    for path in [
+        segmented::plan_path(&temp_file),
+        resume::validator_path(&temp_file),
+        delta::failed_marker_path(&temp_file),
+        temp_file.clone(),
+    ] {
+        if path.exists() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    if let Some(game_dir) = temp_file.parent() {
+        let _ = fs::remove_dir(game_dir);
+    }
+}
+
+// Report how far a blocking extraction, patch or verification has got until it finishes
+async fn watch_extraction<T>(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    progress: &ExtractProgress,
+    task: tokio::task::JoinHandle<Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+    status: DownloadStatus,
+    label: &str,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    watch_progress(progress, task, |extracted, total, percentage| async move {
+        set_download_status(
+            host,
+            download_state,
+            status,
+            &format!("{}... {:.0}%", label, percentage),
+            serde_json::json!({
+                "extracted_bytes": extracted,
+                "total_bytes": total,
+                "percentage": percentage
+            }),
+        )
+        .await;
+    })
+    .await
+}
+
+// Wait for a blocking task, passing its progress to `report` twice a second as
+// (processed bytes, total bytes, percentage)
+async fn watch_progress<T, F, R>(
+    progress: &ExtractProgress,
+    task: tokio::task::JoinHandle<Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+    mut report: R,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>>
+where
+    R: FnMut(u64, u64, f32) -> F,
+    F: std::future::Future<Output = ()>,
+{
+    tokio::pin!(task);
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(500));
+    loop {
+        tokio::select! {
+            result = &mut task => return result?,
+            _ = ticker.tick() => {
+                let processed = progress.extracted_bytes.load(Ordering::Relaxed);
+                let total = progress.total_bytes.load(Ordering::Relaxed);
+                let percentage = if total > 0 {
+                    (processed as f32 / total as f32) * 100.0
+                } else {
+                    0.0
+                };
+                report(processed, total, percentage).await;
+            }
+        }
+    }
+}
+
+// Version recorded by the last successful install in `game_dir`, if any
+fn installed_version(game_dir: &Path) -> Option<String> {
+    let content = fs::read_to_string(game_dir.join("vapr_game_info.json")).ok()?;
+    let info: serde_json::Value = serde_json::from_str(&content).ok()?;
+    info["version"].as_str().map(|v| v.to_string())
+}
+
+// Fetch a patch archive to `patch_path`, hashing it on the way. Patches are small next
+// to the full game, so a pause simply throws the partial file away.
+async fn download_patch(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    client: &reqwest::Client,
+    url: &str,
+    patch_path: &Path,
+    throttle: &Throttle,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let response = client.get(url).send().await?.error_for_status()?;
+
+    *download_state.total_bytes.lock().await = response.content_length().unwrap_or(0);
+    download_state.throughput.lock().await.reset();
+    download_state.record_downloaded(0).await;
+
+    let mut file = File::create(patch_path).await?;
+    let mut hasher = Sha256::new();
+    let mut stream = response.bytes_stream();
+    let mut downloaded = 0u64;
+    let mut last_update = std::time::Instant::now();
+
+    use futures_util::StreamExt;
+    while let Some(chunk) = stream.next().await {
+        if download_state.is_paused.load(Ordering::Relaxed) {
+            drop(file);
+            let _ = tokio::fs::remove_file(patch_path).await;
+            return Err(Box::new(DownloadInterrupted::Paused));
+        }
+
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        downloaded += chunk.len() as u64;
+
+        download_state.record_downloaded(downloaded).await;
+        throttle.consume(chunk.len() as u64).await;
+
+        if last_update.elapsed() > Duration::from_millis(100) {
+            last_update = std::time::Instant::now();
+            emit_download_progress(host, download_state, throttle).await?;
+        }
+    }
+
+    file.flush().await?;
+    Ok(checksum::to_hex(hasher))
+}
+
+// Back off before retry number `attempt`, showing the download as retrying meanwhile.
+// Fails with Paused if the user pauses during the wait.
+async fn wait_before_retry(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    retry_policy: &RetryPolicy,
+    attempt: u32,
+    error: &(dyn std::error::Error + Send + Sync),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let delay = retry_policy.delay_for(attempt);
+    eprintln!(
+        "Download of {} failed ({}), retry {}/{} in {:?}",
+        download_state.game_name, error, attempt, retry_policy.max_attempts, delay
+    );
+
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Retrying,
+        &format!(
+            "Connection lost, retrying in {}s ({}/{})...",
+            delay.as_secs().max(1),
+            attempt,
+            retry_policy.max_attempts
+        ),
+        serde_json::json!({
+            "attempt": attempt,
+            "max_attempts": retry_policy.max_attempts,
+            "retry_in_ms": delay.as_millis() as u64,
+            "error": error.to_string()
+        }),
+    )
+    .await;
+
+    // Sleep in small steps so a pause during the backoff takes effect right away
+    let deadline = std::time::Instant::now() + delay;
+    while std::time::Instant::now() < deadline {
+        if download_state.is_paused.load(Ordering::Relaxed) {
+            return Err(Box::new(DownloadInterrupted::Paused));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Downloading,
+        "Reconnecting...",
+        serde_json::json!({}),
+    )
+    .await;
+    Ok(())
+}
+
+// Update an installed game with a delta from the API instead of the full archive,
+// returning the patched copy staged for commit_install. Returns Ok(None) when this isn't
+// an update or the server has no patch for it. With a manifest, the patch may only write
+// files the manifest lists, so everything it adds gets verified.
+async fn try_delta_update(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    client: &reqwest::Client,
+    manifest: Option<&ReleaseManifest>,
+    game_dir: &Path,
+    temp_file_path: &Path,
+    throttle: &Throttle,
+) -> Result<Option<StagedInstall>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(installed) = installed_version(game_dir) else {
+        return Ok(None);
+    };
+    let Some(target) = manifest
+        .map(|manifest| manifest.version.clone())
+        .or_else(|| download_state.version.clone())
+    else {
+        return Ok(None);
+    };
+    // A partial full archive or the marker means an earlier patch attempt already fell back
+    if installed == target || temp_file_path.exists() || delta::failed_marker_path(temp_file_path).exists() {
+        return Ok(None);
+    }
+
+    let Some(offer) = delta::find_patch(client, &download_state.game_id, &installed, &target).await? else {
+        return Ok(None);
+    };
+
+    let patch_path = delta::patch_path(temp_file_path);
+    let fetched = tokio::select! {
+        result = download_patch(host, download_state, client, &offer.url, &patch_path, throttle) => result,
+        _ = download_state.cancel_token.cancelled() => Err(Box::new(DownloadInterrupted::Cancelled) as Box<_>),
+    };
+    let actual = match fetched {
+        Ok(actual) => actual,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&patch_path).await;
+            return Err(e);
+        }
+    };
+
+    if let Some(expected) = offer.sha256.as_deref() {
+        if !checksum::matches(expected, &actual) {
+            let _ = tokio::fs::remove_file(&patch_path).await;
+            return Err(Box::new(checksum::ChecksumMismatch {
+                expected: expected.to_string(),
+                actual,
+            }));
+        }
+    }
+
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Extracting,
+        "Applying update patch...",
+        serde_json::json!({}),
+    )
+    .await;
+
+    let staged = match stage_install(game_dir, temp_file_path).await {
+        Ok(staged) => staged,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&patch_path).await;
+            return Err(e);
+        }
+    };
+
+    let progress = Arc::new(ExtractProgress::default());
+    let patching = tokio::task::spawn_blocking({
+        let patch_path = patch_path.clone();
+        let game_dir = staged.dir().to_path_buf();
+        let cancel_token = download_state.cancel_token.clone();
+        let progress = progress.clone();
+        move || delta::apply_patch(&patch_path, &game_dir, &installed, &target, &cancel_token, &progress)
+    });
+    let result = watch_extraction(
+        host,
+        download_state,
+        &progress,
+        patching,
+        DownloadStatus::Extracting,
+        "Applying update patch",
+    )
+    .await;
+
+    let _ = tokio::fs::remove_file(&patch_path).await;
+    let unlisted = match (result, manifest) {
+        (Ok(written), Some(manifest)) => written.into_iter().find(|path| !manifest.lists(path)),
+        (Ok(_), None) => None,
+        (Err(e), _) => {
+            discard_staged(staged).await;
+            return Err(e);
+        }
+    };
+    if let Some(path) = unlisted {
+        discard_staged(staged).await;
+        return Err(Box::new(delta::PatchFailed(format!("{} is not part of the release", path))));
+    }
+    Ok(Some(staged))
+}
+
+// Fetch one chunk into the store. Chunks are small, so a pause throws away the partial
+// chunk and the resumed download starts it over.
+async fn fetch_chunk(
+    source: Box<dyn DownloadSource>,
+    chunk: ManifestChunk,
+    store: Arc<ChunkStore>,
+    is_paused: &AtomicBool,
+    downloaded: &AtomicU64,
+    throttle: &Throttle,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut stream = source.fetch_from(0, None).await?.stream;
+
+    let mut data = Vec::with_capacity(chunk.size as usize);
+    use futures_util::StreamExt;
+    while let Some(bytes) = stream.next().await {
+        if is_paused.load(Ordering::Relaxed) {
+            return Err(Box::new(DownloadInterrupted::Paused));
+        }
+
+        // Chunks are held in memory until they are hashed, so never take more than the
+        // manifest says a chunk holds
+        let bytes = bytes?;
+        if data.len() as u64 + bytes.len() as u64 > chunk.size {
+            return Err(Box::new(VerificationFailed(format!(
+                "chunk {} is larger than the {} bytes the manifest lists",
+                chunk.sha256, chunk.size
+            ))));
+        }
+        data.extend_from_slice(&bytes);
+        downloaded.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        throttle.consume(bytes.len() as u64).await;
+    }
+
+    tokio::task::spawn_blocking(move || store.insert(&chunk, &data)).await?
+}
+
+// Resolves a chunk hash to where it can be fetched from
+type ChunkSourceFn<'a> = dyn Fn(&str) -> Result<Box<dyn DownloadSource>, String> + Sync + 'a;
+
+// Download whichever of `chunks` the store doesn't have yet, several at once
+async fn download_chunks(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    settings: &DownloadSettings,
+    chunk_source: &ChunkSourceFn<'_>,
+    store: &Arc<ChunkStore>,
+    chunks: &[ManifestChunk],
+    throttle: &Throttle,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use futures_util::{StreamExt, TryStreamExt};
+
+    let (remaining, stored): (Vec<_>, Vec<_>) = chunks
+        .iter()
+        .cloned()
+        .partition(|chunk| !store.contains(&chunk.sha256));
+    let downloaded = AtomicU64::new(stored.iter().map(|chunk| chunk.size).sum());
+    let downloaded = &downloaded;
+
+    let fetches = futures_util::stream::iter(remaining)
+        .map(|chunk| async move {
+            let source = chunk_source(&chunk.sha256)?;
+            fetch_chunk(source, chunk, store.clone(), &download_state.is_paused, downloaded, throttle).await
+        })
+        .buffer_unordered(settings.download_connections.max(1))
+        .try_collect::<Vec<()>>();
+    tokio::pin!(fetches);
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
+    let mut last_journal_write = std::time::Instant::now();
+
+    let result = loop {
+        tokio::select! {
+            result = &mut fetches => break result,
+            _ = ticker.tick() => {
+                download_state.record_downloaded(downloaded.load(Ordering::Relaxed)).await;
+                emit_download_progress(host, download_state, throttle).await?;
+
+                if last_journal_write.elapsed() > Duration::from_secs(5) {
+                    last_journal_write = std::time::Instant::now();
+                    persist_downloads(&host.state).await;
+                }
+            }
+        }
+    };
+
+    download_state.record_downloaded(downloaded.load(Ordering::Relaxed)).await;
+    result.map(|_| ())
+}
+
+// Install a chunked build: only the files that differ from the manifest are rebuilt, and
+// only the chunks of those that aren't already in the store or the current install are
+// downloaded. The files are assembled in a staged copy of the install for commit_install.
+async fn install_chunked(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    client: &reqwest::Client,
+    manifest: &ReleaseManifest,
+    game_dir: &Path,
+    temp_file_path: &Path,
+    throttle: &Throttle,
+) -> Result<StagedInstall, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = manifest.chunk_base_url.clone().unwrap_or_default();
+    let settings = host.state.settings.read().await.clone();
+    let store = Arc::new(ChunkStore::open(&host.state.chunk_leases)?);
+    // Held until the chunks are released, so another install can't remove them meanwhile
+    let lease = store.lease(manifest);
+    let previous = manifest::load_installed(game_dir);
+
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Verifying,
+        "Checking installed files...",
+        serde_json::json!({}),
+    )
+    .await;
+
+    let progress = Arc::new(ExtractProgress::default());
+    let planning = tokio::task::spawn_blocking({
+        let store = store.clone();
+        let manifest = manifest.clone();
+        let previous = previous.clone();
+        let game_dir = game_dir.to_path_buf();
+        let cancel_token = download_state.cancel_token.clone();
+        let progress = progress.clone();
+        move || chunks::plan_install(&store, &manifest, previous.as_ref(), &game_dir, &cancel_token, &progress)
+    });
+    let plan = watch_extraction(
+        host,
+        download_state,
+        &progress,
+        planning,
+        DownloadStatus::Verifying,
+        "Checking installed files",
+    )
+    .await?;
+    if plan.stale_files.is_empty() {
+        return stage_install(game_dir, temp_file_path).await;
+    }
+
+    let missing_bytes: u64 = plan.missing.iter().map(|chunk| chunk.size).sum();
+    let stale_bytes: u64 = plan.stale_files.iter().map(|file| file.size).sum();
+    diskspace::ensure_available(store.root(), missing_bytes)?;
+    diskspace::ensure_available(game_dir, stale_bytes)?;
+
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Downloading,
+        &format!("Downloading {} changed files...", plan.stale_files.len()),
+        serde_json::json!({
+            "changed_files": plan.stale_files.len(),
+            "missing_chunks": plan.missing.len()
+        }),
+    )
+    .await;
+
+    *download_state.total_bytes.lock().await = missing_bytes;
+    download_state.throughput.lock().await.reset();
+
+    let retry_policy = RetryPolicy::from_settings(&settings);
+    let mut attempt = 0;
+    let mut bytes_at_last_failure = 0;
+
+    let chunk_source = |sha256: &str| {
+        let url = format!("{}/{}", base_url.trim_end_matches('/'), sha256);
+        source::source_for(&url, client, settings.mirror_directory.as_deref())
+    };
+
+    let fetch = async {
+        loop {
+            let Err(e) =
+                download_chunks(host, download_state, &settings, &chunk_source, &store, &plan.missing, throttle)
+                    .await
+            else {
+                return Ok(());
             };
 
-            let eta = if speed > 0.0 {
-                ((total_size - downloaded) as f64 / speed) as u64
-            } else {
-                0
+            // Any progress since the last failure earns a fresh retry budget
+            let downloaded_now = *download_state.downloaded_bytes.lock().await;
+            if downloaded_now > bytes_at_last_failure {
+                attempt = 0;
+            }
+            bytes_at_last_failure = downloaded_now;
+
+            if download_state.is_paused.load(Ordering::Relaxed)
+                || !retry::classify(&*e).is_transient()
+                || attempt >= retry_policy.max_attempts
+            {
+                return Err(e);
+            }
+
+            attempt += 1;
+            wait_before_retry(host, download_state, &retry_policy, attempt, &*e).await?;
+        }
+    };
+
+    let fetched: Result<(), Box<dyn std::error::Error + Send + Sync>> = tokio::select! {
+        result = fetch => result,
+        _ = download_state.cancel_token.cancelled() => Err(Box::new(DownloadInterrupted::Cancelled)),
+    };
+    if let Err(e) = fetched {
+        // Keep the chunks for a resume, but not for a cancelled install
+        if download_state.cancel_token.is_cancelled() {
+            store.release(lease, &plan.missing);
+        }
+        return Err(e);
+    }
+
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Extracting,
+        "Assembling game files...",
+        serde_json::json!({}),
+    )
+    .await;
+
+    let staged = stage_install(game_dir, temp_file_path).await?;
+    let progress = Arc::new(ExtractProgress::default());
+    let assembly = tokio::task::spawn_blocking({
+        let store = store.clone();
+        let manifest = manifest.clone();
+        let stale_files = plan.stale_files.clone();
+        let game_dir = staged.dir().to_path_buf();
+        let cancel_token = download_state.cancel_token.clone();
+        let progress = progress.clone();
+        move || {
+            chunks::assemble_files(
+                &store,
+                &manifest,
+                &stale_files,
+                previous.as_ref(),
+                &game_dir,
+                &cancel_token,
+                &progress,
+            )
+        }
+    });
+    let assembled = watch_extraction(
+        host,
+        download_state,
+        &progress,
+        assembly,
+        DownloadStatus::Extracting,
+        "Assembling game files",
+    )
+    .await;
+    if let Err(e) = assembled {
+        discard_staged(staged).await;
+        return Err(e);
+    }
+
+    let used: Vec<ManifestChunk> = plan.stale_files.iter().flat_map(|file| file.chunks.clone()).collect();
+    store.release(lease, &used);
+    Ok(staged)
+}
+
+// Mirror the live install into a staging directory, leaving out this download's temp
+// files and the install records that finish_install writes afresh
+async fn stage_install(
+    game_dir: &Path,
+    temp_file_path: &Path,
+) -> Result<StagedInstall, Box<dyn std::error::Error + Send + Sync>> {
+    let exclude = vec![
+        temp_file_path.to_path_buf(),
+        segmented::plan_path(temp_file_path),
+        resume::validator_path(temp_file_path),
+        delta::patch_path(temp_file_path),
+        delta::failed_marker_path(temp_file_path),
+        game_dir.join("vapr_game_info.json"),
+        game_dir.join(manifest::INSTALLED_MANIFEST_NAME),
+        game_dir.join(integrity::FILE_RECORD_NAME),
+    ];
+    let game_dir = game_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || StagedInstall::prepare(&game_dir, &exclude)).await?
+}
+
+async fn discard_staged(staged: StagedInstall) {
+    let _ = tokio::task::spawn_blocking(move || staged.discard()).await;
+}
+
+// Verify a staged build against its manifest, if it has one, and swap it in for the live
+// install, or throw it away
+async fn commit_install(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    manifest: Option<&ReleaseManifest>,
+    staged: StagedInstall,
+) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(manifest) = manifest {
+        if let Err(e) = verify_install(host, download_state, manifest, staged.dir()).await {
+            discard_staged(staged).await;
+            return Err(e);
+        }
+    }
+    if let Err(e) = record_installed_files(host, download_state, manifest, staged.dir()).await {
+        discard_staged(staged).await;
+        return Err(e);
+    }
+    let kept_versions = host.state.settings.read().await.kept_versions;
+    finish_install(download_state, manifest, staged, kept_versions).await
+}
+
+// Check the installed files against the signed manifest. Nothing is recorded as installed
+// unless this passes.
+async fn verify_install(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    manifest: &ReleaseManifest,
+    game_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    set_download_status(
+        host,
+        download_state,
+        DownloadStatus::Verifying,
+        "Verifying installed files...",
+        serde_json::json!({}),
+    )
+    .await;
+
+    let progress = Arc::new(ExtractProgress::default());
+    let verification = tokio::task::spawn_blocking({
+        let manifest = manifest.clone();
+        let game_dir = game_dir.to_path_buf();
+        let cancel_token = download_state.cancel_token.clone();
+        let progress = progress.clone();
+        move || manifest::verify_files(&manifest, &game_dir, &cancel_token, &progress)
+    });
+
+    watch_extraction(
+        host,
+        download_state,
+        &progress,
+        verification,
+        DownloadStatus::Verifying,
+        "Verifying installed files",
+    )
+    .await
+}
+
+// Write down every file of the verified build so verify_game can check it later
+async fn record_installed_files(
+    host: &DownloadHost,
+    download_state: &DownloadState,
+    manifest: Option<&ReleaseManifest>,
+    game_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let progress = Arc::new(ExtractProgress::default());
+    let recording = tokio::task::spawn_blocking({
+        let manifest = manifest.cloned();
+        let game_dir = game_dir.to_path_buf();
+        let cancel_token = download_state.cancel_token.clone();
+        let progress = progress.clone();
+        move || {
+            let record = integrity::record(&game_dir, manifest.as_ref(), &cancel_token, &progress)?;
+            integrity::save(&record, &game_dir)?;
+            Ok(())
+        }
+    });
+
+    watch_extraction(
+        host,
+        download_state,
+        &progress,
+        recording,
+        DownloadStatus::Verifying,
+        "Recording installed files",
+    )
+    .await
+}
+
+// New improved download function that writes directly to disk
+async fn download_file_to_disk(
+    host: DownloadHost,
+    download_state: DownloadState,
+) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+    let client = host.state.http_client.read().await.clone();
+
+    // Setup paths
+    let game_dir = download_state
+        .library_root
+        .join(safe_game_name(&download_state.game_name));
+    fs::create_dir_all(&game_dir)?;
+
+    let temp_file_path = download_temp_path(&download_state.library_root, &download_state.game_name);
+
+    let settings = host.state.settings.read().await.clone();
+
+    // Every build is checked against the manifest signed by the release key; the archive
+    // hash it carries takes precedence over the one the frontend passed in. Only local
+    // files and mirrors may skip it, and only when the user has opted in.
+    let is_local = download_state.download_urls().iter().all(|url| source::is_local(url));
+    let manifest = if is_local && settings.allow_unsigned_local_installs {
+        eprintln!(
+            "Installing {} from local files without a signed manifest (allow_unsigned_local_installs is on)",
+            download_state.game_name
+        );
+        None
+    } else {
+        Some(manifest::fetch_verified(&client, &download_state.game_id, download_state.version.as_deref()).await?)
+    };
+
+    let expected_sha256 = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.archive_sha256.clone())
+        .or_else(|| download_state.expected_sha256.clone());
+    let mut refetched = false;
+    let mut force_single_stream = false;
+
+    let retry_policy = RetryPolicy::from_settings(&settings);
+    let source = source::source_for_mirrors(
+        &download_state.download_urls(),
+        &client,
+        settings.mirror_directory.as_deref(),
+        expected_sha256.is_some(),
+    )?;
+
+    let throttle = download_throttle(&host.state, &download_state);
+    // Chunked builds skip the archive entirely
+    if let Some(manifest) = manifest.as_ref().filter(|manifest| manifest.is_chunked()) {
+        let staged = install_chunked(
+            &host,
+            &download_state,
+            &client,
+            manifest,
+            &game_dir,
+            &temp_file_path,
+            &throttle,
+        )
+        .await?;
+        return commit_install(&host, &download_state, Some(manifest), staged).await;
+    }
+
+    // Updates from the API try a delta against the installed version before the full
+    // archive. A patched build that doesn't verify falls back to the full archive too.
+    let delta = if is_local {
+        Ok(None)
+    } else {
+        let patched = try_delta_update(
+            &host,
+            &download_state,
+            &client,
+            manifest.as_ref(),
+            &game_dir,
+            &temp_file_path,
+            &throttle,
+        )
+        .await;
+        match patched {
+            Ok(Some(staged)) => commit_install(&host, &download_state, manifest.as_ref(), staged).await.map(Some),
+            other => other.map(|_| None),
+        }
+    };
+    match delta {
+        Ok(Some(installed)) => return Ok(installed),
+        Ok(None) => {}
+        Err(e) if interrupt::interruption(&*e).is_some() => return Err(e),
+        Err(e) => {
+            eprintln!(
+                "Delta update for {} failed, downloading the full archive: {}",
+                download_state.game_name, e
+            );
+            tokio::fs::write(delta::failed_marker_path(&temp_file_path), b"").await?;
+            set_download_status(
+                &host,
+                &download_state,
+                DownloadStatus::Downloading,
+                "Patch failed, downloading the full update...",
+                serde_json::json!({}),
+            )
+            .await;
+            download_state.throughput.lock().await.reset();
+        }
+    }
+
+    let mut attempt = 0;
+    let mut bytes_at_last_failure = 0;
+
+    // Everything up to a complete, verified archive on disk. Cancelling drops this future,
+    // which closes the HTTP connections and stops any backoff wait straight away.
+    let fetch = async {
+        let total_size = loop {
+            // Resume a segmented download if one was in flight, otherwise decide how to fetch.
+            // Only fresh downloads get split; a partial single-stream file keeps appending.
+            let mut plan = SegmentPlan::load(&segmented::plan_path(&temp_file_path));
+            if plan.is_none() && !temp_file_path.exists() && !force_single_stream {
+                let connections = settings.download_connections;
+                if connections > 1 {
+                    match source.probe().await {
+                        Ok(support) if support.accepts_ranges => {
+                            plan = support
+                                .total_size
+                                .filter(|size| *size >= segmented::MIN_SEGMENTED_SIZE)
+                                .map(|size| SegmentPlan::new(size, connections, support.validator));
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Range probe failed, using a single connection: {}", e),
+                    }
+                }
+            }
+
+            let fetched = match plan {
+                Some(plan) => {
+                    match download_segmented(
+                        &host,
+                        &download_state,
+                        &*source,
+                        &temp_file_path,
+                        &throttle,
+                        plan,
+                    )
+                    .await
+                    {
+                        // Ranges land out of order, so the file is hashed once it is complete
+                        Ok(total_size) => Ok((total_size, None)),
+                        Err(e) if e.is::<ResumeInvalidated>() => {
+                            // The partial ranges are useless now; start over on a single connection
+                            eprintln!("{}, restarting {}", e, download_state.game_name);
+                            let _ = tokio::fs::remove_file(segmented::plan_path(&temp_file_path)).await;
+                            let _ = tokio::fs::remove_file(&temp_file_path).await;
+                            force_single_stream = true;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                None => {
+                    match download_single_stream(
+                        &host,
+                        &download_state,
+                        &*source,
+                        &temp_file_path,
+                        &throttle,
+                        expected_sha256.is_some(),
+                    )
+                    .await
+                    {
+                        Err(e) if e.is::<ResumeInvalidated>() => {
+                            eprintln!("{}, restarting {}", e, download_state.game_name);
+                            let _ = tokio::fs::remove_file(resume::validator_path(&temp_file_path)).await;
+                            let _ = tokio::fs::remove_file(&temp_file_path).await;
+                            continue;
+                        }
+                        result => result,
+                    }
+                }
             };
 
-            let progress = DownloadProgress {
-                download_id: download_state.id.clone(),
-                game_id: download_state.game_id.clone(),
-                game_name: download_state.game_name.clone(),
-                game_cover: download_state.game_cover.clone(),
-                downloaded,
-                total: total_size,
-                percentage: (downloaded as f32 / total_size as f32) * 100.0,
-                speed: speed / 1024.0 / 1024.0, // Convert to MB/s
-                eta,
+            let (total_size, streamed_digest) = match fetched {
+                Ok(result) => result,
+                Err(e) => {
+                    // Any progress since the last failure earns a fresh retry budget
+                    let downloaded_now = *download_state.downloaded_bytes.lock().await;
+                    if downloaded_now > bytes_at_last_failure {
+                        attempt = 0;
+                    }
+                    bytes_at_last_failure = downloaded_now;
+
+                    let kind = retry::classify(&*e);
+                    if download_state.is_paused.load(Ordering::Relaxed)
+                        || !kind.is_transient()
+                        || attempt >= retry_policy.max_attempts
+                    {
+                        return Err(e);
+                    }
+
+                    attempt += 1;
+                    wait_before_retry(&host, &download_state, &retry_policy, attempt, &*e).await?;
+                    continue;
+                }
+            };
+
+            let Some(expected) = expected_sha256.as_deref() else {
+                break total_size;
+            };
+
+            set_download_status(
+                &host,
+                &download_state,
+                DownloadStatus::Verifying,
+                "Verifying download...",
+                serde_json::json!({}),
+            )
+            .await;
+
+            let actual = match streamed_digest {
+                Some(digest) => digest,
+                None => checksum::hash_file(&temp_file_path).await?,
             };
 
-            app_handle.emit("download-progress", &progress)?;
+            if checksum::matches(expected, &actual) {
+                break total_size;
+            }
+
+            // Corrupt or truncated archive: throw it away and fetch it again, once
+            tokio::fs::remove_file(&temp_file_path).await?;
+            if refetched {
+                return Err(Box::new(checksum::ChecksumMismatch {
+                    expected: expected.to_string(),
+                    actual,
+                }));
+            }
+            refetched = true;
+
+            eprintln!(
+                "Checksum mismatch for {} (expected {}, got {}), re-downloading",
+                download_state.game_name, expected, actual
+            );
+            set_download_status(
+                &host,
+                &download_state,
+                DownloadStatus::Downloading,
+                "Download was corrupted, downloading again...",
+                serde_json::json!({}),
+            )
+            .await;
+            download_state.throughput.lock().await.reset();
+        };
+        Ok::<u64, Box<dyn std::error::Error + Send + Sync>>(total_size)
+    };
+
+    let total_size = tokio::select! {
+        result = fetch => result?,
+        _ = download_state.cancel_token.cancelled() => {
+            return Err(Box::new(DownloadInterrupted::Cancelled));
         }
-    }
-
-    // Ensure all data is written
-    file.flush().await?;
-    drop(file);
+    };
 
     // Final progress update
-    app_handle.emit("download-progress", &DownloadProgress {
+    host.emit("download-progress", &DownloadProgress {
         download_id: download_state.id.clone(),
         game_id: download_state.game_id.clone(),
         game_name: download_state.game_name.clone(),
@@ -401,100 +1967,146 @@ async fn download_file_to_disk(
         percentage: 100.0,
         speed: 0.0,
         eta: 0,
+        rate_limit: None,
     })?;
 
     // Extract the downloaded file
-    app_handle.emit("download-status", serde_json::json!({
-        "download_id": download_state.id.clone(),
-        "game_id": download_state.game_id.clone(),
-        "status": "Extracting game files...",
-        "message": "Extracting game files..."
-    }))?;
+    set_download_status(
+        &host,
+        &download_state,
+        DownloadStatus::Extracting,
+        "Extracting game files...",
+        serde_json::json!({}),
+    )
+    .await;
 
-    // Read file for extraction (this is necessary for zip extraction)
-    let zip_data = tokio::fs::read(&temp_file_path).await?;
-    let cursor = Cursor::new(zip_data);
-    let mut archive = zip::ZipArchive::new(cursor)?;
+    // Unpack into an empty staging directory so a failed or cancelled extraction never
+    // touches the live install, and files the new build dropped don't carry over
+    let staged = {
+        let game_dir = game_dir.clone();
+        tokio::task::spawn_blocking(move || StagedInstall::prepare_empty(&game_dir)).await??
+    };
+    let progress = Arc::new(ExtractProgress::default());
+    let extraction = tokio::task::spawn_blocking({
+        let archive_path = temp_file_path.clone();
+        let game_dir = staged.dir().to_path_buf();
+        let cancel_token = download_state.cancel_token.clone();
+        let progress = progress.clone();
+        move || extract::extract_archive(&archive_path, &game_dir, &cancel_token, &progress)
+    });
 
-    let total_files = archive.len();
+    let extracted = watch_extraction(
+        &host,
+        &download_state,
+        &progress,
+        extraction,
+        DownloadStatus::Extracting,
+        "Extracting game files",
+    )
+    .await;
+    if let Err(e) = extracted {
+        discard_staged(staged).await;
+        return Err(e);
+    }
 
-    for i in 0..total_files {
-        // Emit extraction progress
-        if i % 10 == 0 {
-            app_handle.emit("download-status", serde_json::json!({
-                "download_id": download_state.id.clone(),
-                "game_id": download_state.game_id.clone(),
-                "status": "Extracting game files...",
-                "message": format!("Extracting file {}/{}", i + 1, total_files)
-            }))?;
-        }
+    // Clean up temp file
+    tokio::fs::remove_file(&temp_file_path).await?;
+    let _ = tokio::fs::remove_file(delta::failed_marker_path(&temp_file_path)).await;
 
-        let mut file = archive.by_index(i)?;
+    commit_install(&host, &download_state, manifest.as_ref(), staged).await
+}
 
-        let outpath = match file.enclosed_name() {
-            Some(path) => game_dir.join(path),
-            None => continue,
+// Record the installed version and its manifest in the staged build, then swap it in
+async fn finish_install(
+    download_state: &DownloadState,
+    manifest: Option<&ReleaseManifest>,
+    staged: StagedInstall,
+    kept_versions: usize,
+) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+    let game_dir = staged.game_dir().to_path_buf();
+
+    let recorded = async {
+        // The manifest names the executable; without one, look for it. It keeps its place
+        // relative to the game once swapped in.
+        let executable = match manifest.and_then(|manifest| manifest.executable_path()) {
+            Some(relative) => staged.dir().join(relative),
+            None => find_game_executable(staged.dir())?,
         };
+        let executable = game_dir.join(executable.strip_prefix(staged.dir()).unwrap_or(&executable));
+
+        // Save game info
+        let game_info = serde_json::json!({
+            "id": download_state.game_id,
+            "name": download_state.game_name,
+            "install_path": game_dir.to_string_lossy(),
+            "executable": executable.to_string_lossy(),
+            "version": manifest
+                .map(|manifest| manifest.version.clone())
+                .or_else(|| download_state.version.clone())
+                .unwrap_or_else(|| "1.0.0".to_string()),
+            "installed_at": chrono::Utc::now().to_rfc3339(),
+        });
 
-        if file.name().ends_with('/') {
-            fs::create_dir_all(&outpath)?;
-        } else {
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p)?;
-                }
-            }
-
-            let mut outfile = fs::File::create(&outpath)?;
-            std::io::copy(&mut file, &mut outfile)?;
-        }
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
-            }
+        let game_info_path = staged.dir().join("vapr_game_info.json");
+        tokio::fs::write(
+            &game_info_path,
+            serde_json::to_string_pretty(&game_info).unwrap(),
+        ).await?;
+        if let Some(manifest) = manifest {
+            manifest::save_installed(manifest, staged.dir()).await?;
         }
+        Ok::<PathBuf, Box<dyn std::error::Error + Send + Sync>>(executable)
     }
+    .await;
 
-    // Clean up temp file
-    tokio::fs::remove_file(&temp_file_path).await?;
-
-    // Find executable
-    let executable = find_game_executable(&game_dir)?;
-
-    // Save game info
-    let game_info = serde_json::json!({
-        "id": download_state.game_id,
-        "name": download_state.game_name,
-        "install_path": game_dir.to_string_lossy(),
-        "executable": executable.to_string_lossy(),
-        "version": download_state.version.clone().unwrap_or_else(|| "1.0.0".to_string()),
-        "installed_at": chrono::Utc::now().to_rfc3339(),
-    });
+    let executable = match recorded {
+        Ok(executable) => executable,
+        Err(e) => {
+            discard_staged(staged).await;
+            return Err(e);
+        }
+    };
 
-    let game_info_path = game_dir.join("vapr_game_info.json");
-    tokio::fs::write(
-        &game_info_path,
-        serde_json::to_string_pretty(&game_info).unwrap(),
-    ).await?;
+    tokio::task::spawn_blocking(move || staged.commit(kept_versions))
+        .await?
+        .map_err(|e| format!("Failed to replace the installed game (is it running?): {}", e))?;
 
     Ok((game_dir.to_string_lossy().to_string(), executable.to_string_lossy().to_string()))
 }
 
 #[tauri::command]
 async fn pause_download(
+    app_handle: tauri::AppHandle,
     state: State<'_, AppState>,
     download_id: String,
 ) -> Result<(), String> {
-    let downloads = state.downloads.lock().await;
-    if let Some(download) = downloads.get(&download_id) {
-        download.is_paused.store(true, Ordering::Relaxed);
-        Ok(())
-    } else {
-        Err("Download not found".to_string())
+    let download = {
+        let downloads = state.downloads.lock().await;
+        downloads
+            .get(&download_id)
+            .cloned()
+            .ok_or_else(|| "Download not found".to_string())?
+    };
+
+    // Verifying and extracting work on the finished archive and can't stop half-way
+    let status = *download.status.lock().await;
+    if !status.is_pausable() {
+        return Err(format!("Download can't be paused while {}", status.as_str()));
     }
+
+    download.is_paused.store(true, Ordering::Relaxed);
+    set_download_status(
+        &app_handle,
+        &download,
+        DownloadStatus::Paused,
+        "Download paused",
+        serde_json::json!({}),
+    )
+    .await;
+    state.scheduler.lock().await.park(&download_id);
+    persist_downloads(&state).await;
+    state.scheduler_wakeup.notify_one();
+    Ok(())
 }
 
 #[tauri::command]
@@ -509,13 +2121,15 @@ async fn resume_download(
     };
 
     if let Some(download) = download_state {
-        download.is_paused.store(false, Ordering::Relaxed);
-        *download.start_time.lock().await = Some(std::time::Instant::now());
+        // Only paused or failed downloads go back in the queue
+        let status = *download.status.lock().await;
+        if !matches!(status, DownloadStatus::Paused | DownloadStatus::Failed) {
+            return Err(format!("Download is already {}", status.as_str()));
+        }
 
-        // Restart download from where it left off
-        tauri::async_runtime::spawn(async move {
-            perform_download(app_handle, download).await;
-        });
+        // Requeue it; the scheduler restarts it from where it left off once a slot is free,
+        // or once its schedule allows
+        release_download(&app_handle, &state, &download, serde_json::json!({})).await;
 
         Ok(())
     } else {
@@ -523,22 +2137,70 @@ async fn resume_download(
     }
 }
 
+// Change or clear (None) when a download may run. Paused and failed downloads keep their
+// status and follow the new schedule once resumed.
 #[tauri::command]
-async fn cancel_download(
+async fn reschedule_download(
+    app_handle: tauri::AppHandle,
     state: State<'_, AppState>,
     download_id: String,
+    schedule: Option<DownloadSchedule>,
 ) -> Result<(), String> {
-    let mut downloads = state.downloads.lock().await;
-    if let Some(download) = downloads.remove(&download_id) {
-        download.is_paused.store(true, Ordering::Relaxed);
+    if let Some(schedule) = &schedule {
+        schedule.validate()?;
+    }
+
+    let download = {
+        let downloads = state.downloads.lock().await;
+        downloads
+            .get(&download_id)
+            .cloned()
+            .ok_or_else(|| "Download not found".to_string())?
+    };
+
+    let status = *download.status.lock().await;
+    if matches!(status, DownloadStatus::Verifying | DownloadStatus::Extracting) {
+        return Err(format!("Download can't be rescheduled while {}", status.as_str()));
+    }
+
+    *download.schedule.lock().await = schedule;
+
+    if matches!(status, DownloadStatus::Paused | DownloadStatus::Failed) {
+        persist_downloads(&state).await;
+    } else {
+        release_download(&app_handle, &state, &download, serde_json::json!({})).await;
+    }
 
-        // Clean up temporary files
-        let safe_game_name = download.game_name.replace(" ", "_").replace(":", "");
-        let vapr_games_dir = get_games_directory()?;
-        let temp_file = vapr_games_dir.join(&safe_game_name).join(format!("{}.download", safe_game_name));
+    Ok(())
+}
 
-        if temp_file.exists() {
-            let _ = fs::remove_file(temp_file);
+#[tauri::command]
+async fn cancel_download(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    download_id: String,
+) -> Result<(), String> {
+    let removed = state.downloads.lock().await.remove(&download_id);
+    if let Some(download) = removed {
+        download.cancel_token.cancel();
+
+        let was_running = {
+            let mut scheduler = state.scheduler.lock().await;
+            let running = scheduler.is_running(&download_id);
+            scheduler.remove(&download_id);
+            running
+        };
+        persist_downloads(&state).await;
+        state.scheduler_wakeup.notify_one();
+
+        // A running task cleans up after itself and reports the cancellation once it has
+        // stopped; otherwise there is only the partial download on disk to remove
+        if !was_running {
+            discard_partial_download(&download.library_root, &download.game_name);
+            let _ = app_handle.emit("download-cancelled", serde_json::json!({
+                "download_id": download.id,
+                "game_id": download.game_id
+            }));
         }
 
         Ok(())
@@ -547,6 +2209,146 @@ async fn cancel_download(
     }
 }
 
+// Move a download to `position` in the queue (0 = next to start)
+#[tauri::command]
+async fn move_download_in_queue(
+    state: State<'_, AppState>,
+    download_id: String,
+    position: usize,
+) -> Result<(), String> {
+    state.scheduler.lock().await.move_to(&download_id, position)?;
+    persist_downloads(&state).await;
+    state.scheduler_wakeup.notify_one();
+    Ok(())
+}
+
+#[tauri::command]
+async fn prioritize_download(
+    state: State<'_, AppState>,
+    download_id: String,
+) -> Result<(), String> {
+    move_download_in_queue(state, download_id, 0).await
+}
+
+// Cap download bandwidth in bytes/sec (0 = unlimited). With a download id the limit
+// applies to that download only, otherwise to all downloads combined. Takes effect
+// immediately for running downloads.
+#[tauri::command]
+async fn set_download_rate_limit(
+    state: State<'_, AppState>,
+    download_id: Option<String>,
+    bytes_per_sec: u64,
+) -> Result<(), String> {
+    match download_id {
+        Some(download_id) => {
+            {
+                let downloads = state.downloads.lock().await;
+                let download = downloads
+                    .get(&download_id)
+                    .ok_or_else(|| "Download not found".to_string())?;
+                download.rate_limiter.set_rate(bytes_per_sec);
+            }
+            persist_downloads(&state).await;
+        }
+        None => {
+            let mut settings = state.settings.write().await;
+            settings.global_rate_limit = bytes_per_sec;
+            settings::save_settings(&settings)?;
+            state.global_rate_limiter.set_rate(bytes_per_sec);
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_download_settings(state: State<'_, AppState>) -> Result<DownloadSettings, String> {
+    Ok(state.settings.read().await.clone())
+}
+
+#[tauri::command]
+async fn update_download_settings(
+    state: State<'_, AppState>,
+    mut settings: DownloadSettings,
+) -> Result<(), String> {
+    // Refuse settings the client can't be built from, e.g. a malformed proxy URL
+    let http_client = http::build_client(&settings)?;
+    // Library folders are checked as they're added, so only add_library_root and
+    // remove_library_root change them
+    settings.library_roots = state.settings.read().await.library_roots.clone();
+    settings::save_settings(&settings)?;
+    *state.http_client.write().await = http_client;
+    state
+        .scheduler
+        .lock()
+        .await
+        .set_max_concurrent(settings.max_concurrent_downloads);
+    state.global_rate_limiter.set_rate(settings.global_rate_limit);
+    *state.settings.write().await = settings;
+
+    // A higher limit may free up slots for queued downloads
+    state.scheduler_wakeup.notify_one();
+    Ok(())
+}
+
+// Every library folder with its free space, default first
+#[tauri::command]
+async fn get_library_roots(state: State<'_, AppState>) -> Result<Vec<serde_json::Value>, String> {
+    let roots = library::roots(&*state.settings.read().await);
+    Ok(roots
+        .iter()
+        .enumerate()
+        .map(|(index, root)| {
+            serde_json::json!({
+                "path": root.to_string_lossy(),
+                "is_default": index == 0,
+                "available_bytes": diskspace::available_space(root).ok()
+            })
+        })
+        .collect())
+}
+
+#[tauri::command]
+async fn add_library_root(state: State<'_, AppState>, path: String) -> Result<String, String> {
+    let mut settings = state.settings.write().await;
+    let root = library::validate_new_root(&settings, Path::new(&path))?;
+
+    let mut updated = settings.clone();
+    updated.library_roots.push(root.clone());
+    settings::save_settings(&updated)?;
+    *settings = updated;
+
+    Ok(root.to_string_lossy().to_string())
+}
+
+// Stop using a library folder. Games still in it would vanish from the library, so they
+// have to be moved or uninstalled first.
+#[tauri::command]
+async fn remove_library_root(state: State<'_, AppState>, path: String) -> Result<(), String> {
+    let mut settings = state.settings.write().await;
+    let Some(index) = library::registered_index(&settings, Path::new(&path)) else {
+        return Err(format!("{} is not a library folder", path));
+    };
+    // The root as registered, which is how games and downloads refer to it
+    let path = settings.library_roots[index].clone();
+
+    {
+        let downloads = state.downloads.lock().await;
+        if downloads.values().any(|download| library::same_dir(&download.library_root, &path)) {
+            return Err("Finish or cancel the downloads into this folder first".to_string());
+        }
+    }
+    if library::game_dirs().iter().any(|game_dir| game_dir.starts_with(&path)) {
+        return Err("Move or uninstall the games in this folder first".to_string());
+    }
+
+    let mut updated = settings.clone();
+    updated.library_roots.remove(index);
+    settings::save_settings(&updated)?;
+    *settings = updated;
+
+    Ok(())
+}
+
 #[tauri::command]
 async fn open_downloads_window(app: tauri::AppHandle) -> Result<(), String> {
     // Check if downloads window already exists
@@ -622,6 +2424,17 @@ fn find_exe_recursive(dir: &Path, current_depth: u32, max_depth: u32) -> Option<
 }
 
 fn resolve_game_id_from_exe_dir(exe_dir: &Path) -> Option<String> {
+    // Inside a library folder the game's own directory holds the info file
+    if let Some(game_dir) = library::game_dir_of(exe_dir) {
+        let game_id = fs::read_to_string(game_dir.join("vapr_game_info.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .and_then(|json| json.get("id").and_then(|v| v.as_str()).map(|id| id.to_string()));
+        if game_id.is_some() {
+            return game_id;
+        }
+    }
+
     // Search up to 3 parent levels for vapr_game_info.json and read the game id
     let mut current: Option<&Path> = Some(exe_dir);
     for _ in 0..4 {
@@ -644,11 +2457,19 @@ fn resolve_game_id_from_exe_dir(exe_dir: &Path) -> Option<String> {
     None
 }
 
-#[tauri::command]
-async fn launch_game(window: tauri::Window, executable_path: String) -> Result<bool, String> {
+// A launched game, tracked so its playtime can be reported when it exits
+struct GameSession {
+    child: std::process::Child,
+    game_id: Option<String>,
+    executable_path: String,
+    started_at: chrono::DateTime<chrono::Utc>,
+    start_instant: std::time::Instant,
+}
+
+fn start_game(executable_path: &str) -> Result<GameSession, String> {
     use std::process::Command;
 
-    let path = PathBuf::from(&executable_path);
+    let path = PathBuf::from(executable_path);
 
     if !path.exists() {
         return Err("Executable not found".to_string());
@@ -659,33 +2480,51 @@ async fn launch_game(window: tauri::Window, executable_path: String) -> Result<b
         .ok_or_else(|| "Failed to get executable directory".to_string())?;
 
     // Try to resolve game_id from nearby vapr_game_info.json
-    let game_id_opt = resolve_game_id_from_exe_dir(exe_dir);
+    let game_id = resolve_game_id_from_exe_dir(exe_dir);
 
     // Start process and monitor duration
     let started_at = chrono::Utc::now();
     let start_instant = std::time::Instant::now();
 
-    let mut child = Command::new(&executable_path)
+    let child = Command::new(executable_path)
         .current_dir(exe_dir)
         .spawn()
         .map_err(|e| format!("Failed to launch game: {}", e))?;
 
-    let window_clone = window.clone();
-    tauri::async_runtime::spawn_blocking(move || {
-        // Wait for the game process to exit
-        let _ = child.wait();
+    Ok(GameSession {
+        child,
+        game_id,
+        executable_path: executable_path.to_string(),
+        started_at,
+        start_instant,
+    })
+}
+
+impl GameSession {
+    // Block until the game exits and describe the session
+    fn wait(mut self) -> serde_json::Value {
+        let _ = self.child.wait();
         let ended_at = chrono::Utc::now();
-        let duration_secs = start_instant.elapsed().as_secs();
+        let duration_secs = self.start_instant.elapsed().as_secs();
 
-        // Emit event to frontend so it can record the session
-        let payload = serde_json::json!({
-            "game_id": game_id_opt,
-            "started_at": started_at.to_rfc3339(),
+        serde_json::json!({
+            "game_id": self.game_id,
+            "started_at": self.started_at.to_rfc3339(),
             "ended_at": ended_at.to_rfc3339(),
             "duration_seconds": duration_secs,
-            "executable_path": executable_path
-        });
-        let _ = window_clone.emit("playtime-session", payload);
+            "executable_path": self.executable_path
+        })
+    }
+}
+
+#[tauri::command]
+async fn launch_game(window: tauri::Window, executable_path: String) -> Result<bool, String> {
+    let session = start_game(&executable_path)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        // Emit event to frontend so it can record the session
+        let payload = session.wait();
+        let _ = window.emit("playtime-session", payload);
     });
 
     Ok(true)
@@ -693,26 +2532,12 @@ async fn launch_game(window: tauri::Window, executable_path: String) -> Result<b
 
 #[tauri::command]
 async fn get_installed_games() -> Result<Vec<serde_json::Value>, String> {
-    let vapr_games_dir = get_games_directory()?;
-
-    if !vapr_games_dir.exists() {
-        return Ok(vec![]);
-    }
-
     let mut games = Vec::new();
 
-    if let Ok(entries) = fs::read_dir(&vapr_games_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                let info_file = path.join("vapr_game_info.json");
-                if info_file.exists() {
-                    if let Ok(content) = fs::read_to_string(&info_file) {
-                        if let Ok(game_info) = serde_json::from_str::<serde_json::Value>(&content) {
-                            games.push(game_info);
-                        }
-                    }
-                }
+    for path in library::game_dirs() {
+        if let Ok(content) = fs::read_to_string(path.join("vapr_game_info.json")) {
+            if let Ok(game_info) = serde_json::from_str::<serde_json::Value>(&content) {
+                games.push(game_info);
             }
         }
     }
@@ -722,24 +2547,23 @@ async fn get_installed_games() -> Result<Vec<serde_json::Value>, String> {
 
 #[tauri::command]
 async fn uninstall_game(game_id: String) -> Result<bool, String> {
-    let vapr_games_dir = get_games_directory()?;
+    let path = find_game_directory(&game_id)?;
+    fs::remove_dir_all(&path)
+        .map_err(|e| format!("Failed to remove game directory: {}", e))?;
+    let _ = fs::remove_dir_all(versions::versions_dir(&path));
+    Ok(true)
+}
 
-    if let Ok(entries) = fs::read_dir(&vapr_games_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                let info_file = path.join("vapr_game_info.json");
-                if info_file.exists() {
-                    if let Ok(content) = fs::read_to_string(&info_file) {
-                        if let Ok(game_info) = serde_json::from_str::<serde_json::Value>(&content) {
-                            if game_info.get("id").and_then(|v| v.as_str()) == Some(&game_id) {
-                                fs::remove_dir_all(&path)
-                                    .map_err(|e| format!("Failed to remove game directory: {}", e))?;
-                                return Ok(true);
-                            }
-                        }
-                    }
-                }
+// Install directory of the game with `game_id`, found by its vapr_game_info.json in any
+// library folder
+fn find_game_directory(game_id: &str) -> Result<PathBuf, String> {
+    for path in library::game_dirs() {
+        let Ok(content) = fs::read_to_string(path.join("vapr_game_info.json")) else {
+            continue;
+        };
+        if let Ok(game_info) = serde_json::from_str::<serde_json::Value>(&content) {
+            if game_info.get("id").and_then(|v| v.as_str()) == Some(game_id) {
+                return Ok(path);
             }
         }
     }
@@ -747,6 +2571,143 @@ async fn uninstall_game(game_id: String) -> Result<bool, String> {
     Err("Game not found".to_string())
 }
 
+// Put a game back to a version kept from before its last update, the newest kept one
+// unless `version` is given. The version it replaces is kept in turn if kept_versions
+// allows. Returns the game info of the restored install.
+#[tauri::command]
+async fn rollback_game(
+    state: State<'_, AppState>,
+    game_id: String,
+    version: Option<String>,
+) -> Result<serde_json::Value, String> {
+    {
+        let downloads = state.downloads.lock().await;
+        if downloads.values().any(|download| download.game_id == game_id) {
+            return Err("Finish or cancel the game's download before rolling it back".to_string());
+        }
+    }
+
+    let game_dir = find_game_directory(&game_id)?;
+    let kept_versions = state.settings.read().await.kept_versions;
+
+    tokio::task::spawn_blocking(move || versions::rollback(&game_dir, version.as_deref(), kept_versions))
+        .await
+        .map_err(|e| format!("Rollback failed: {}", e))?
+}
+
+// Report how far a blocking task on an installed game has got until it finishes
+async fn watch_game_task<T>(
+    app_handle: &tauri::AppHandle,
+    event: &str,
+    game_id: &str,
+    progress: &ExtractProgress,
+    task: tokio::task::JoinHandle<Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    watch_progress(progress, task, |processed, total, percentage| {
+        let _ = app_handle.emit(event, serde_json::json!({
+            "game_id": game_id,
+            "processed_bytes": processed,
+            "total_bytes": total,
+            "percentage": percentage
+        }));
+        std::future::ready(())
+    })
+    .await
+}
+
+// Check an installed game against the files recorded when it was installed. Reports
+// recorded files that are missing or modified, and files that were never installed.
+#[tauri::command]
+async fn verify_game(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    game_id: String,
+) -> Result<serde_json::Value, String> {
+    {
+        let downloads = state.downloads.lock().await;
+        if downloads.values().any(|download| download.game_id == game_id) {
+            return Err("Finish or cancel the game's download before verifying it".to_string());
+        }
+    }
+
+    let game_dir = find_game_directory(&game_id)?;
+    let record = integrity::load(&game_dir)
+        .ok_or_else(|| "No file record for this game; reinstall it to verify it".to_string())?;
+
+    let progress = Arc::new(ExtractProgress::default());
+    let task = {
+        let progress = progress.clone();
+        tokio::task::spawn_blocking(move || {
+            integrity::verify(&game_dir, &record, &CancellationToken::new(), &progress)
+        })
+    };
+    let report = watch_game_task(&app_handle, "game-verify-progress", &game_id, &progress, task)
+        .await
+        .map_err(|e| format!("Verification failed: {}", e))?;
+
+    let result = serde_json::json!({
+        "game_id": game_id,
+        "intact": report.is_intact(),
+        "missing": report.missing,
+        "modified": report.modified,
+        "extra": report.extra
+    });
+    let _ = app_handle.emit("game-verified", result.clone());
+    Ok(result)
+}
+
+// Move an installed game into another library folder, reporting progress as it goes.
+// Returns the game info with its new paths.
+#[tauri::command]
+async fn move_game(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    game_id: String,
+    library_root: String,
+) -> Result<serde_json::Value, String> {
+    {
+        let downloads = state.downloads.lock().await;
+        if downloads.values().any(|download| download.game_id == game_id) {
+            return Err("Finish or cancel the game's download before moving it".to_string());
+        }
+    }
+
+    let target_root = library::resolve(&*state.settings.read().await, Some(Path::new(&library_root)))?;
+    let game_dir = find_game_directory(&game_id)?;
+    if game_dir.parent().is_some_and(|root| library::same_dir(root, &target_root)) {
+        return Err("The game is already in that library folder".to_string());
+    }
+
+    let progress = Arc::new(ExtractProgress::default());
+    let task = {
+        let progress = progress.clone();
+        let game_dir = game_dir.clone();
+        tokio::task::spawn_blocking(move || relocate::move_game(&game_dir, &target_root, &progress))
+    };
+    let moved = watch_game_task(&app_handle, "game-move-progress", &game_id, &progress, task)
+        .await
+        .map_err(|e| format!("Move failed: {}", e))?;
+
+    // The files have moved whatever happened to the game info, so report the new paths
+    // even if it couldn't be read or rewritten
+    let mut game_info = fs::read_to_string(moved.game_dir.join("vapr_game_info.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_else(|| serde_json::json!({ "id": game_id }));
+    relocate::point_at(&mut game_info, &game_dir, &moved.game_dir);
+    if let Some(warning) = &moved.warning {
+        game_info["warning"] = serde_json::json!(warning);
+    }
+
+    let _ = app_handle.emit("game-moved", serde_json::json!({
+        "game_id": game_id,
+        "install_path": game_info["install_path"],
+        "executable": game_info["executable"],
+        "warning": moved.warning
+    }));
+    Ok(game_info)
+}
+
 // New WebSocket-related commands
 #[tauri::command]
 async fn update_sdk_user_info(
@@ -824,15 +2785,61 @@ pub fn run() {
             resume_download,
             cancel_download,
             open_downloads_window,
-            get_active_downloads
+            get_active_downloads,
+            move_download_in_queue,
+            prioritize_download,
+            get_download_settings,
+            update_download_settings,
+            set_download_rate_limit,
+            reschedule_download,
+            rollback_game,
+            get_library_roots,
+            add_library_root,
+            remove_library_root,
+            move_game,
+            verify_game
         ])
         .setup(|app| {
-            // Create app state for downloads
+            // Create app state for downloads, restoring any queue left over from the last run
+            let settings = settings::load_settings();
+            for root in library::roots(&settings) {
+                staging::recover(&root);
+            }
+            let mut scheduler = DownloadScheduler::new(settings.max_concurrent_downloads);
+
+            let default_root = get_games_directory()?;
+            let journal = DownloadJournal::open()?;
+            let mut restored = HashMap::new();
+            for entry in journal.load() {
+                scheduler.track(&entry.id);
+                restored.insert(entry.id.clone(), DownloadState::from_journal_entry(entry, &default_root));
+            }
+
+            let global_rate_limiter = Arc::new(RateLimiter::new(settings.global_rate_limit));
+
+            // A proxy or CA bundle that has gone bad since it was saved shouldn't stop the app
+            // from starting; fall back to a plain client until the settings are fixed
+            let http_client = http::build_client(&settings).unwrap_or_else(|e| {
+                eprintln!("Ignoring network settings: {}", e);
+                http::build_client(&DownloadSettings::default())
+                    .expect("default HTTP client settings are valid")
+            });
+
             let app_state = AppState {
-                downloads: Arc::new(Mutex::new(HashMap::new())),
+                downloads: Arc::new(Mutex::new(restored)),
+                journal: Some(Arc::new(journal)),
+                scheduler: Arc::new(Mutex::new(scheduler)),
+                scheduler_wakeup: Arc::new(Notify::new()),
+                settings: Arc::new(RwLock::new(settings)),
+                global_rate_limiter,
+                http_client: Arc::new(RwLock::new(http_client)),
+                chunk_leases: ChunkLeases::default(),
             };
             app.manage(app_state);
 
+            tauri::async_runtime::spawn(run_download_scheduler(app.handle().clone()));
+            tauri::async_runtime::spawn(run_schedule_timer(app.handle().clone()));
+
             // Create JS bridge and WebSocket server after Tauri has initialized
             let bridge = Arc::new(SdkBridge::new(app.handle().clone()));
             let ws_server = Arc::new(WebSocketServer::new(bridge.clone()));
@@ -849,4 +2856,162 @@ pub fn run() {
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    struct NoEvents;
+
+    impl EventSink for NoEvents {
+        fn emit_event(&self, _event: &str, _payload: JsonValue) {}
+    }
+
+    fn host_with(settings: DownloadSettings) -> DownloadHost {
+        let http_client = http::build_client(&settings).unwrap();
+        DownloadHost {
+            events: Arc::new(NoEvents),
+            state: AppState {
+                downloads: Arc::new(Mutex::new(HashMap::new())),
+                journal: None,
+                scheduler: Arc::new(Mutex::new(DownloadScheduler::new(settings.max_concurrent_downloads))),
+                scheduler_wakeup: Arc::new(Notify::new()),
+                global_rate_limiter: Arc::new(RateLimiter::new(0)),
+                settings: Arc::new(RwLock::new(settings)),
+                http_client: Arc::new(RwLock::new(http_client)),
+                chunk_leases: ChunkLeases::default(),
+            },
+        }
+    }
+
+    fn download_of(url: &str, library_root: &Path, expected_sha256: Option<String>) -> DownloadState {
+        DownloadState {
+            id: "download".to_string(),
+            game_id: "game".to_string(),
+            game_name: "Test Game".to_string(),
+            game_cover: None,
+            download_url: url.to_string(),
+            mirror_urls: Vec::new(),
+            version: Some("1.0.0".to_string()),
+            expected_sha256,
+            library_root: library_root.to_path_buf(),
+            rate_limiter: Arc::new(RateLimiter::new(0)),
+            is_paused: Arc::new(AtomicBool::new(false)),
+            cancel_token: CancellationToken::new(),
+            downloaded_bytes: Arc::new(Mutex::new(0)),
+            total_bytes: Arc::new(Mutex::new(0)),
+            status: Arc::new(Mutex::new(DownloadStatus::Downloading)),
+            throughput: Arc::new(Mutex::new(ThroughputMeter::default())),
+            schedule: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // A zip holding the game's executable and one data file
+    fn write_game_archive(path: &Path) -> String {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut archive = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        let options = zip::write::FileOptions::default();
+        archive.start_file("Game.exe", options).unwrap();
+        archive.write_all(b"executable").unwrap();
+        archive.start_file("data/level1.dat", options).unwrap();
+        archive.write_all(b"level data").unwrap();
+        archive.finish().unwrap();
+
+        checksum::hash_file_blocking(path).unwrap()
+    }
+
+    fn assert_installed(library_root: &Path, install_path: &str, executable: &str) {
+        let game_dir = library_root.join("Test_Game");
+        assert_eq!(Path::new(install_path), game_dir);
+        assert_eq!(Path::new(executable), game_dir.join("Game.exe"));
+        assert_eq!(fs::read(game_dir.join("data/level1.dat")).unwrap(), b"level data");
+        assert!(!download_temp_path(library_root, "Test Game").exists());
+        assert_eq!(installed_version(&game_dir).as_deref(), Some("1.0.0"));
+
+        let record = integrity::load(&game_dir).unwrap();
+        let report = integrity::verify(&game_dir, &record, &CancellationToken::new(), &ExtractProgress::default())
+            .unwrap();
+        assert_eq!(record.files.len(), 2);
+        assert!(report.is_intact());
+    }
+
+    // Local installs in these tests have no signed manifest to check against
+    fn unsigned_local_settings() -> DownloadSettings {
+        DownloadSettings {
+            allow_unsigned_local_installs: true,
+            ..DownloadSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn installs_from_a_local_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let library_root = dir.path().join("library");
+        fs::create_dir(&library_root).unwrap();
+        let archive_path = dir.path().join("downloads").join("game.zip");
+        let sha256 = write_game_archive(&archive_path);
+
+        let url = reqwest::Url::from_file_path(&archive_path).unwrap().to_string();
+        let download = download_of(&url, &library_root, Some(sha256));
+        let (install_path, executable) = download_file_to_disk(host_with(unsigned_local_settings()), download)
+            .await
+            .unwrap();
+
+        assert_installed(&library_root, &install_path, &executable);
+    }
+
+    #[tokio::test]
+    async fn installs_from_a_mirror_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let library_root = dir.path().join("library");
+        fs::create_dir(&library_root).unwrap();
+        let mirror_directory = dir.path().join("mirror");
+        write_game_archive(&mirror_directory.join("cdn.example.com/games/game.zip"));
+
+        let settings = DownloadSettings {
+            mirror_directory: Some(mirror_directory),
+            ..unsigned_local_settings()
+        };
+        let download = download_of("mirror://cdn.example.com/games/game.zip", &library_root, None);
+        let (install_path, executable) = download_file_to_disk(host_with(settings), download).await.unwrap();
+
+        assert_installed(&library_root, &install_path, &executable);
+    }
+
+    #[tokio::test]
+    async fn archives_failing_their_checksum_are_not_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let library_root = dir.path().join("library");
+        fs::create_dir(&library_root).unwrap();
+        let archive_path = dir.path().join("game.zip");
+        write_game_archive(&archive_path);
+
+        let url = reqwest::Url::from_file_path(&archive_path).unwrap().to_string();
+        let download = download_of(&url, &library_root, Some("0".repeat(64)));
+        let result = download_file_to_disk(host_with(unsigned_local_settings()), download).await;
+
+        assert!(result.unwrap_err().downcast_ref::<checksum::ChecksumMismatch>().is_some());
+        assert!(!library_root.join("Test_Game").join("Game.exe").exists());
+    }
+
+    #[tokio::test]
+    async fn local_files_need_a_manifest_unless_the_user_opts_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let library_root = dir.path().join("library");
+        fs::create_dir(&library_root).unwrap();
+        let archive_path = dir.path().join("game.zip");
+        let sha256 = write_game_archive(&archive_path);
+
+        // Nothing listens here, so fetching the manifest fails without touching the network
+        let settings = DownloadSettings {
+            proxy_url: Some("http://127.0.0.1:9".to_string()),
+            ..DownloadSettings::default()
+        };
+        let url = reqwest::Url::from_file_path(&archive_path).unwrap().to_string();
+        let download = download_of(&url, &library_root, Some(sha256));
+
+        assert!(download_file_to_disk(host_with(settings), download).await.is_err());
+        assert!(!library_root.join("Test_Game").join("Game.exe").exists());
+    }
+}
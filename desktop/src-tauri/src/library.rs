@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::settings::{self, DownloadSettings};
+use crate::staging;
+
+// Library roots are the folders games are installed into: the default one under the VAPR
+// data directory, which always exists, plus any the user registers, e.g. on a second
+// drive. Every game is a directory directly inside one of them.
+
+// All roots, default first. Registered roots that aren't there right now (an unplugged
+// drive) are left out until they come back.
+pub fn roots(settings: &DownloadSettings) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = crate::get_games_directory().into_iter().collect();
+    for root in &settings.library_roots {
+        if root.is_dir() && !roots.iter().any(|known| same_dir(known, root)) {
+            roots.push(root.clone());
+        }
+    }
+    roots
+}
+
+// Roots per the saved settings, for scans that run without the app state
+pub fn saved_roots() -> Vec<PathBuf> {
+    roots(&settings::load_settings())
+}
+
+// Directory of every installed game, across all roots
+pub fn game_dirs() -> Vec<PathBuf> {
+    saved_roots()
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && !staging::is_internal_dir(path)
+                && path.join("vapr_game_info.json").exists()
+        })
+        .collect()
+}
+
+// The root a download installs into: `requested` if given, which must be registered,
+// otherwise the default one
+pub fn resolve(settings: &DownloadSettings, requested: Option<&Path>) -> Result<PathBuf, String> {
+    let Some(requested) = requested else {
+        return crate::get_games_directory();
+    };
+
+    roots(settings)
+        .into_iter()
+        .find(|root| same_dir(root, requested))
+        .ok_or_else(|| format!("{} is not an available library folder", requested.display()))
+}
+
+// The game directory `path` is in, if it is inside a root: the directory right below
+// the root, however deep `path` sits
+pub fn game_dir_of(path: &Path) -> Option<PathBuf> {
+    let canonical_path = fs::canonicalize(path).ok();
+    saved_roots().into_iter().find_map(|root| {
+        let relative = path.strip_prefix(&root).ok().map(Path::to_path_buf).or_else(|| {
+            let canonical_root = fs::canonicalize(&root).ok()?;
+            Some(canonical_path.as_ref()?.strip_prefix(canonical_root).ok()?.to_path_buf())
+        })?;
+        relative.components().next().map(|component| root.join(component))
+    })
+}
+
+// Check `path` can be registered as a new root and return it in canonical form. Roots
+// may not overlap, or the same game would be found twice.
+pub fn validate_new_root(settings: &DownloadSettings, path: &Path) -> Result<PathBuf, String> {
+    if !path.is_absolute() {
+        return Err("Library folder must be an absolute path".to_string());
+    }
+
+    fs::create_dir_all(path).map_err(|e| format!("Failed to create library folder: {}", e))?;
+    let path = fs::canonicalize(path).map_err(|e| format!("Failed to resolve library folder: {}", e))?;
+
+    // Installs write here; find out now rather than halfway through a download
+    let probe = path.join(".vapr-write-test");
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| format!("Library folder is not writable: {}", e))?;
+
+    let registered = roots(settings).into_iter().chain(settings.library_roots.iter().cloned());
+    for root in registered {
+        let root = fs::canonicalize(&root).unwrap_or(root);
+        if path.starts_with(&root) || root.starts_with(&path) {
+            return Err(format!("Library folder overlaps with {}", root.display()));
+        }
+    }
+
+    Ok(path)
+}
+
+// Index of the registered root `path` names. Roots are stored canonical, so `path` is
+// matched the same way: a trailing slash, a symlink or different casing still finds it.
+pub fn registered_index(settings: &DownloadSettings, path: &Path) -> Option<usize> {
+    settings.library_roots.iter().position(|root| same_dir(root, path))
+}
+
+pub fn same_dir(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (fs::canonicalize(a), fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_roots_are_found_however_they_are_spelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap().join("Library");
+        fs::create_dir(&root).unwrap();
+        let settings = DownloadSettings {
+            library_roots: vec![dir.path().join("Other"), root.clone()],
+            ..DownloadSettings::default()
+        };
+
+        assert_eq!(registered_index(&settings, &root), Some(1));
+        let trailing_slash = format!("{}/", root.display());
+        assert_eq!(registered_index(&settings, Path::new(&trailing_slash)), Some(1));
+        assert_eq!(registered_index(&settings, &root.join("..").join("Library")), Some(1));
+        assert_eq!(registered_index(&settings, &dir.path().join("Missing")), None);
+
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&root, &link).unwrap();
+            assert_eq!(registered_index(&settings, &link), Some(1));
+        }
+    }
+}
@@ -2,5 +2,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    // CLI commands print JSON and exit without opening a window. On Windows release builds
+    // there is no console, but output that is piped or redirected still arrives.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = vapr_desktop_lib::cli::run(&args) {
+        std::process::exit(code);
+    }
+
     vapr_desktop_lib::run()
 }
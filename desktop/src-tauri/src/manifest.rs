@@ -0,0 +1,320 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use tokio_util::sync::CancellationToken;
+
+use crate::checksum;
+use crate::extract::{self, ExtractProgress};
+use crate::interrupt::check_cancelled;
+
+const MANIFEST_API_URL: &str = "https://vapr.club/api/games";
+
+// Copy of the manifest a game was installed from, kept next to its files
+pub const INSTALLED_MANIFEST_NAME: &str = "vapr_manifest.json";
+
+// Hex-encoded ed25519 key that release manifests are signed with. Pinned at build time so
+// a compromised API or CDN can't substitute its own. A build without it still compiles,
+// but rejects every manifest, so nothing can be installed from the API.
+const MANIFEST_PUBLIC_KEY: Option<&str> = option_env!("VAPR_MANIFEST_PUBLIC_KEY");
+
+// As served by the API: the manifest JSON exactly as signed, plus its signature
+#[derive(Debug, Deserialize)]
+struct SignedManifest {
+    manifest: String,
+    signature: String,
+}
+
+// Describes one build of a game: what the archive is and what it must install.
+// Chunked builds have no archive; each file lists the chunks it is made of, served
+// at <chunk_base_url>/<sha256>.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    pub game_id: String,
+    pub version: String,
+    // The file to launch, one of `files`
+    pub executable: String,
+    #[serde(default)]
+    pub archive_sha256: Option<String>,
+    #[serde(default)]
+    pub chunk_base_url: Option<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    // Unix permission bits, for executables in chunked builds
+    #[serde(default)]
+    pub mode: Option<u32>,
+    #[serde(default)]
+    pub chunks: Vec<ManifestChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestChunk {
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl ReleaseManifest {
+    pub fn is_chunked(&self) -> bool {
+        self.chunk_base_url.is_some()
+    }
+
+    // The executable relative to the game directory
+    pub fn executable_path(&self) -> Option<PathBuf> {
+        extract::enclosed_path(Path::new(&self.executable))
+    }
+
+    // Whether `path`, relative to the game directory, is one of the build's files
+    pub fn lists(&self, path: &str) -> bool {
+        let Some(path) = extract::enclosed_path(Path::new(path)) else {
+            return false;
+        };
+        self.files
+            .iter()
+            .any(|file| extract::enclosed_path(Path::new(&file.path)).as_ref() == Some(&path))
+    }
+}
+
+// The manifest or the installed files didn't check out; the build must not be installed
+#[derive(Debug)]
+pub struct VerificationFailed(pub String);
+
+impl fmt::Display for VerificationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Build verification failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for VerificationFailed {}
+
+// Download the manifest for `version` of a game (the latest if None) and check its
+// signature and that it describes the build we asked for. A build without a manifest
+// can't be verified, so it fails like one whose manifest doesn't check out.
+pub async fn fetch_verified(
+    client: &reqwest::Client,
+    game_id: &str,
+    version: Option<&str>,
+) -> Result<ReleaseManifest, Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = client.get(format!("{}/{}/manifest", MANIFEST_API_URL, game_id));
+    if let Some(version) = version {
+        request = request.query(&[("version", version)]);
+    }
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(Box::new(VerificationFailed("no signed manifest is published for this build".to_string())));
+    }
+    let signed: SignedManifest = response.error_for_status()?.json().await?;
+
+    verify_signature(signed.manifest.as_bytes(), &signed.signature)?;
+
+    let manifest: ReleaseManifest = serde_json::from_str(&signed.manifest)
+        .map_err(|e| VerificationFailed(format!("invalid manifest: {}", e)))?;
+
+    if manifest.game_id != game_id {
+        return Err(Box::new(VerificationFailed(format!(
+            "manifest is for game {}, not {}",
+            manifest.game_id, game_id
+        ))));
+    }
+    if let Some(version) = version.filter(|version| *version != manifest.version) {
+        return Err(Box::new(VerificationFailed(format!(
+            "manifest is for version {}, not {}",
+            manifest.version, version
+        ))));
+    }
+    // Only the listed files are checked after install, so an archive must be pinned by its
+    // hash or it could carry extra files nobody verifies
+    if !manifest.is_chunked() && manifest.archive_sha256.is_none() {
+        return Err(Box::new(VerificationFailed("manifest has no archive hash".to_string())));
+    }
+    if manifest.executable_path().is_none() || !manifest.lists(&manifest.executable) {
+        return Err(Box::new(VerificationFailed(format!(
+            "executable {} is not one of the manifest's files",
+            manifest.executable
+        ))));
+    }
+
+    Ok(manifest)
+}
+
+// The manifest `game_dir` was last installed from. Only written after the install
+// verified, so it describes what should be on disk.
+pub fn load_installed(game_dir: &Path) -> Option<ReleaseManifest> {
+    let content = fs::read_to_string(game_dir.join(INSTALLED_MANIFEST_NAME)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+pub async fn save_installed(manifest: &ReleaseManifest, game_dir: &Path) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(manifest)?;
+    tokio::fs::write(game_dir.join(INSTALLED_MANIFEST_NAME), json).await
+}
+
+fn verify_signature(message: &[u8], signature_hex: &str) -> Result<(), VerificationFailed> {
+    let key_hex = MANIFEST_PUBLIC_KEY.ok_or_else(|| {
+        VerificationFailed("this build has no signing key; set VAPR_MANIFEST_PUBLIC_KEY when building".to_string())
+    })?;
+    verify_signature_with(key_hex, message, signature_hex)
+}
+
+fn verify_signature_with(key_hex: &str, message: &[u8], signature_hex: &str) -> Result<(), VerificationFailed> {
+    let key_bytes: [u8; 32] = hex::decode(key_hex.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| VerificationFailed("pinned signing key is malformed".to_string()))?;
+    let key = VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|e| VerificationFailed(format!("pinned signing key is invalid: {}", e)))?;
+
+    let signature_bytes: [u8; 64] = hex::decode(signature_hex.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| VerificationFailed("malformed manifest signature".to_string()))?;
+
+    key.verify_strict(message, &Signature::from_bytes(&signature_bytes))
+        .map_err(|_| VerificationFailed("manifest signature does not match".to_string()))
+}
+
+// Check every file the manifest lists against what ended up in `game_dir`. Files the
+// manifest doesn't mention are left alone: the archive hash and the patch path check keep
+// the build from adding any. Blocking; run it on spawn_blocking.
+pub fn verify_files(
+    manifest: &ReleaseManifest,
+    game_dir: &Path,
+    cancel_token: &CancellationToken,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let total_bytes = manifest.files.iter().map(|file| file.size).sum();
+    progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+    for file in &manifest.files {
+        check_cancelled(cancel_token)?;
+
+        let relative = extract::enclosed_path(Path::new(&file.path))
+            .ok_or_else(|| VerificationFailed(format!("invalid path in manifest: {}", file.path)))?;
+        let path = game_dir.join(relative);
+
+        let size = fs::metadata(&path)
+            .map_err(|_| VerificationFailed(format!("{} is missing", file.path)))?
+            .len();
+        if size != file.size {
+            return Err(Box::new(VerificationFailed(format!(
+                "{} is {} bytes, expected {}",
+                file.path, size, file.size
+            ))));
+        }
+
+        let actual = checksum::hash_file_blocking(&path)?;
+        if !checksum::matches(&file.sha256, &actual) {
+            return Err(Box::new(VerificationFailed(format!("{} is corrupt", file.path))));
+        }
+
+        progress.extracted_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use sha2::{Digest, Sha256};
+
+    const MESSAGE: &[u8] = br#"{"game_id":"1","version":"1.0.0"}"#;
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn key_hex(key: &SigningKey) -> String {
+        hex::encode(key.verifying_key().to_bytes())
+    }
+
+    fn sign(key: &SigningKey, message: &[u8]) -> String {
+        hex::encode(key.sign(message).to_bytes())
+    }
+
+    fn manifest_for(files: &[(&str, &[u8])]) -> ReleaseManifest {
+        ReleaseManifest {
+            game_id: "1".to_string(),
+            version: "1.0.0".to_string(),
+            executable: files[0].0.to_string(),
+            archive_sha256: None,
+            chunk_base_url: None,
+            files: files
+                .iter()
+                .map(|(path, contents)| ManifestFile {
+                    path: path.to_string(),
+                    size: contents.len() as u64,
+                    sha256: hex::encode(Sha256::digest(contents)),
+                    mode: None,
+                    chunks: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn verify_dir(manifest: &ReleaseManifest, game_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        verify_files(manifest, game_dir, &CancellationToken::new(), &ExtractProgress::default())
+    }
+
+    #[test]
+    fn signatures_from_the_key_verify() {
+        let key = signing_key(1);
+        assert!(verify_signature_with(&key_hex(&key), MESSAGE, &sign(&key, MESSAGE)).is_ok());
+    }
+
+    #[test]
+    fn tampered_manifests_are_rejected() {
+        let key = signing_key(1);
+        let tampered = br#"{"game_id":"1","version":"6.6.6"}"#;
+        assert!(verify_signature_with(&key_hex(&key), tampered, &sign(&key, MESSAGE)).is_err());
+    }
+
+    #[test]
+    fn signatures_from_another_key_are_rejected() {
+        let (key, other) = (signing_key(1), signing_key(2));
+        assert!(verify_signature_with(&key_hex(&key), MESSAGE, &sign(&other, MESSAGE)).is_err());
+    }
+
+    #[test]
+    fn malformed_keys_and_signatures_are_rejected() {
+        let key = signing_key(1);
+        assert!(verify_signature_with("not hex", MESSAGE, &sign(&key, MESSAGE)).is_err());
+        assert!(verify_signature_with(&key_hex(&key), MESSAGE, "abcd").is_err());
+    }
+
+    #[test]
+    fn files_matching_the_manifest_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("game.exe"), b"binary").unwrap();
+
+        assert!(verify_dir(&manifest_for(&[("game.exe", b"binary")]), dir.path()).is_ok());
+    }
+
+    #[test]
+    fn files_differing_from_the_manifest_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest_for(&[("game.exe", b"binary"), ("data.pak", b"data")]);
+        fs::write(dir.path().join("game.exe"), b"binary").unwrap();
+
+        // Missing
+        let error = verify_dir(&manifest, dir.path()).unwrap_err();
+        assert!(error.is::<VerificationFailed>());
+
+        // Same size, different contents
+        fs::write(dir.path().join("data.pak"), b"DATA").unwrap();
+        let error = verify_dir(&manifest, dir.path()).unwrap_err();
+        assert_eq!(error.to_string(), "Build verification failed: data.pak is corrupt");
+
+        // Different size
+        fs::write(dir.path().join("data.pak"), b"data!").unwrap();
+        assert!(verify_dir(&manifest, dir.path()).unwrap_err().is::<VerificationFailed>());
+    }
+}
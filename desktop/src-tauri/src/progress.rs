@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// How far back the speed average looks
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+// Lifecycle of a download as shown to the frontend. Finished and cancelled downloads
+// leave the queue entirely, so they have no state here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    Queued,
+    // Held back until its schedule allows it to run
+    Scheduled,
+    Downloading,
+    Retrying,
+    Paused,
+    Verifying,
+    Extracting,
+    Failed,
+}
+
+impl DownloadStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DownloadStatus::Queued => "queued",
+            DownloadStatus::Scheduled => "scheduled",
+            DownloadStatus::Downloading => "downloading",
+            DownloadStatus::Retrying => "retrying",
+            DownloadStatus::Paused => "paused",
+            DownloadStatus::Verifying => "verifying",
+            DownloadStatus::Extracting => "extracting",
+            DownloadStatus::Failed => "failed",
+        }
+    }
+
+    pub fn can_transition_to(&self, next: DownloadStatus) -> bool {
+        use DownloadStatus::*;
+
+        if *self == next {
+            return true;
+        }
+
+        matches!(
+            (self, next),
+            (Queued, Downloading)
+                | (Queued, Paused)
+                | (Queued, Scheduled)
+                | (Scheduled, Queued)
+                | (Scheduled, Paused)
+                // A schedule window closing stops a running download too
+                | (Downloading, Scheduled)
+                | (Retrying, Scheduled)
+                | (Downloading, Retrying)
+                | (Downloading, Verifying)
+                | (Downloading, Extracting)
+                | (Downloading, Paused)
+                | (Downloading, Failed)
+                | (Retrying, Downloading)
+                | (Retrying, Paused)
+                | (Retrying, Failed)
+                // A checksum mismatch sends the download back for a fresh copy
+                | (Verifying, Downloading)
+                | (Verifying, Extracting)
+                | (Verifying, Failed)
+                | (Extracting, Failed)
+                // Installed files are checked against the release manifest
+                | (Extracting, Verifying)
+                // A delta patch that fails to apply falls back to the full archive
+                | (Extracting, Downloading)
+                | (Paused, Queued)
+                | (Paused, Scheduled)
+                | (Failed, Queued)
+                | (Failed, Scheduled)
+        )
+    }
+
+    // Whether the running task can stop at this point and resume later
+    pub fn is_pausable(&self) -> bool {
+        matches!(
+            self,
+            DownloadStatus::Queued
+                | DownloadStatus::Scheduled
+                | DownloadStatus::Downloading
+                | DownloadStatus::Retrying
+        )
+    }
+}
+
+// Smoothed transfer rate over the last few seconds, fed with the running byte count
+#[derive(Debug, Default)]
+pub struct ThroughputMeter {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ThroughputMeter {
+    // Forget previous samples, e.g. when a download (re)starts after a pause
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn record(&mut self, downloaded: u64) {
+        let now = Instant::now();
+
+        // A restart from a lower offset invalidates the history
+        if self.samples.back().is_some_and(|(_, bytes)| *bytes > downloaded) {
+            self.samples.clear();
+        }
+
+        self.samples.push_back((now, downloaded));
+
+        // Keep one sample older than the window so the rate spans all of it
+        while self.samples.len() > 2
+            && now.duration_since(self.samples[1].0) > THROUGHPUT_WINDOW
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        let (Some((first_at, first_bytes)), Some((last_at, last_bytes))) =
+            (self.samples.front(), self.samples.back())
+        else {
+            return 0.0;
+        };
+
+        // Stalled transfers stop producing samples; don't keep reporting the old rate
+        if last_at.elapsed() > THROUGHPUT_WINDOW {
+            return 0.0;
+        }
+
+        let elapsed = last_at.duration_since(*first_at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+
+        (last_bytes - first_bytes) as f64 / elapsed
+    }
+
+    // Seconds left at the current rate, 0 when unknown
+    pub fn eta(&self, remaining: u64) -> u64 {
+        let speed = self.bytes_per_sec();
+        if speed > 0.0 {
+            (remaining as f64 / speed) as u64
+        } else {
+            0
+        }
+    }
+}
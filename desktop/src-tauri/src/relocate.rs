@@ -0,0 +1,335 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use crate::checksum::{self, ChecksumMismatch};
+use crate::extract::ExtractProgress;
+use crate::{diskspace, staging, versions};
+
+// Where a game ended up. Once its files have moved the move stands, so a game info that
+// couldn't be updated is reported as a warning rather than an error.
+pub struct MovedGame {
+    pub game_dir: PathBuf,
+    pub warning: Option<String>,
+}
+
+// Move the game in `game_dir` into the library folder `target_root`. Within a filesystem
+// the directory is renamed, kept versions and all. Across filesystems every file, kept
+// versions included, is copied and read back before the original is removed. Hard links
+// don't survive the copy, so each kept version then takes as much space as a full
+// install. Blocking; run it on spawn_blocking.
+pub fn move_game(
+    game_dir: &Path,
+    target_root: &Path,
+    progress: &ExtractProgress,
+) -> Result<MovedGame, Box<dyn std::error::Error + Send + Sync>> {
+    let name = game_dir
+        .file_name()
+        .ok_or_else(|| format!("Invalid game directory: {}", game_dir.display()))?;
+    let target = target_root.join(name);
+    if target.exists() || versions::versions_dir(&target).exists() {
+        return Err(format!("{} already exists", target.display()).into());
+    }
+
+    match fs::rename(game_dir, &target) {
+        Ok(()) => {
+            let old_versions = versions::versions_dir(game_dir);
+            if old_versions.exists() {
+                if let Err(e) = fs::rename(&old_versions, versions::versions_dir(&target)) {
+                    let _ = fs::rename(&target, game_dir);
+                    return Err(format!("Failed to move kept versions: {}", e).into());
+                }
+            }
+            progress.total_bytes.store(1, Ordering::Relaxed);
+            progress.extracted_bytes.store(1, Ordering::Relaxed);
+        }
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_across(game_dir, &target, progress)?,
+        Err(e) => return Err(format!("Failed to move game (is it running?): {}", e).into()),
+    }
+
+    let warning = rewrite_game_info(&target, game_dir, &target).err().map(|e| {
+        eprintln!("Moved {} but failed to update its game info: {}", target.display(), e);
+        format!("The game was moved, but its game info could not be updated: {}", e)
+    });
+    // Kept versions are restored into the game's directory, so they need the new paths too
+    for (_, version_dir) in versions::list(&target) {
+        if let Err(e) = rewrite_game_info(&version_dir, game_dir, &target) {
+            eprintln!("Failed to update kept version {}: {}", version_dir.display(), e);
+        }
+    }
+
+    Ok(MovedGame {
+        game_dir: target,
+        warning,
+    })
+}
+
+// Copy the game and its kept versions into staging directories beside the target, then
+// swap. The original is moved aside before the copy goes live, so an interrupted move is
+// undone by staging::recover.
+fn copy_across(
+    game_dir: &Path,
+    target: &Path,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let old_versions = versions::versions_dir(game_dir);
+    let new_versions = versions::versions_dir(target);
+    let mut copies = vec![(game_dir.to_path_buf(), target.to_path_buf())];
+    if old_versions.exists() {
+        copies.push((old_versions.clone(), new_versions.clone()));
+    }
+
+    let total_bytes = copies
+        .iter()
+        .map(|(source, _)| diskspace::dir_size(source))
+        .sum::<io::Result<u64>>()?;
+    diskspace::ensure_available(target, total_bytes)?;
+    progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
+    let staged = copies
+        .iter()
+        .map(|(_, destination)| staging::staging_dir(destination))
+        .collect::<Vec<_>>();
+    let discard_staged = || {
+        for staging_dir in &staged {
+            let _ = fs::remove_dir_all(staging_dir);
+        }
+    };
+    for ((source, _), staging_dir) in copies.iter().zip(&staged) {
+        if staging_dir.exists() {
+            fs::remove_dir_all(staging_dir)?;
+        }
+        fs::create_dir(staging_dir)?;
+        if let Err(e) = copy_dir(source, staging_dir, progress) {
+            discard_staged();
+            return Err(e);
+        }
+    }
+
+    // Kept versions go live first; they mean nothing until the game follows
+    if let Some(versions_staging) = staged.get(1) {
+        if let Err(e) = fs::rename(versions_staging, &new_versions) {
+            discard_staged();
+            return Err(e.into());
+        }
+    }
+
+    let backup_dir = staging::backup_dir(game_dir);
+    if let Err(e) = fs::rename(game_dir, &backup_dir) {
+        discard_staged();
+        let _ = fs::remove_dir_all(&new_versions);
+        return Err(format!("Failed to move game (is it running?): {}", e).into());
+    }
+    if let Err(e) = fs::rename(&staged[0], target) {
+        let _ = fs::rename(&backup_dir, game_dir);
+        discard_staged();
+        let _ = fs::remove_dir_all(&new_versions);
+        return Err(e.into());
+    }
+
+    let _ = fs::remove_dir_all(&backup_dir);
+    let _ = fs::remove_dir_all(&old_versions);
+    Ok(())
+}
+
+fn copy_dir(
+    source: &Path,
+    target: &Path,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        let destination = target.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            fs::create_dir(&destination)?;
+            copy_dir(&path, &destination, progress)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&path, &destination)?;
+        } else {
+            copy_file(&path, &destination, progress)?;
+        }
+    }
+    Ok(())
+}
+
+// Copy one file, hashing it on the way, then read the copy back and check it matches
+fn copy_file(
+    source: &Path,
+    destination: &Path,
+    progress: &ExtractProgress,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut input = fs::File::open(source)?;
+    let mut output = fs::File::create(destination)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output.write_all(&buffer[..read])?;
+        progress.extracted_bytes.fetch_add(read as u64, Ordering::Relaxed);
+    }
+    output.sync_all()?;
+    drop(output);
+    fs::set_permissions(destination, input.metadata()?.permissions())?;
+
+    let expected = hex::encode(hasher.finalize());
+    let actual = checksum::hash_file_blocking(destination)?;
+    if !checksum::matches(&expected, &actual) {
+        return Err(Box::new(ChecksumMismatch { expected, actual }));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(path: &Path, destination: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    std::os::unix::fs::symlink(fs::read_link(path)?, destination)?;
+    Ok(())
+}
+
+// Creating symlinks needs extra privileges on Windows, so copy what the link points at
+#[cfg(not(unix))]
+fn copy_symlink(path: &Path, destination: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if path.is_dir() {
+        fs::create_dir(destination)?;
+        copy_dir(path, destination, &ExtractProgress::default())
+    } else {
+        fs::copy(path, destination)?;
+        Ok(())
+    }
+}
+
+// Point install_path, and the executable if it lies inside the game, at `new_game_dir`.
+// Info that already points there is left as it is.
+pub fn point_at(info: &mut serde_json::Value, old_game_dir: &Path, new_game_dir: &Path) {
+    info["install_path"] = serde_json::json!(new_game_dir.to_string_lossy());
+    let executable = info["executable"]
+        .as_str()
+        .and_then(|executable| Path::new(executable).strip_prefix(old_game_dir).ok())
+        .map(|relative| new_game_dir.join(relative));
+    if let Some(executable) = executable {
+        info["executable"] = serde_json::json!(executable.to_string_lossy());
+    }
+}
+
+fn rewrite_game_info(dir: &Path, old_game_dir: &Path, new_game_dir: &Path) -> io::Result<()> {
+    let info_path = dir.join("vapr_game_info.json");
+    let mut info: serde_json::Value = serde_json::from_str(&fs::read_to_string(&info_path)?)?;
+    point_at(&mut info, old_game_dir, new_game_dir);
+    staging::replace_file(&info_path, serde_json::to_string_pretty(&info)?.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_install(dir: &Path, game_dir: &Path, version: &str) {
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("bin").join("game.exe"), version).unwrap();
+        let info = serde_json::json!({
+            "id": "1",
+            "version": version,
+            "installed_at": "2024-01-01T00:00:00+00:00",
+            "install_path": game_dir.to_string_lossy(),
+            "executable": game_dir.join("bin").join("game.exe").to_string_lossy()
+        });
+        fs::write(dir.join("vapr_game_info.json"), info.to_string()).unwrap();
+    }
+
+    // A game at 2.0.0 in <dir>/old/Game that kept 1.0.0
+    fn game_in(dir: &Path) -> PathBuf {
+        let game_dir = dir.join("old").join("Game");
+        write_install(&game_dir, &game_dir, "2.0.0");
+        write_install(&versions::versions_dir(&game_dir).join("1.0.0"), &game_dir, "1.0.0");
+        game_dir
+    }
+
+    fn info(dir: &Path) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(dir.join("vapr_game_info.json")).unwrap()).unwrap()
+    }
+
+    fn assert_moved(game_dir: &Path, target: &Path) {
+        assert!(!game_dir.exists());
+        assert!(!versions::versions_dir(game_dir).exists());
+        assert_eq!(fs::read_to_string(target.join("bin").join("game.exe")).unwrap(), "2.0.0");
+
+        let kept = versions::list(target);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(fs::read_to_string(kept[0].1.join("bin").join("game.exe")).unwrap(), "1.0.0");
+        for dir in [target, kept[0].1.as_path()] {
+            let info = info(dir);
+            assert_eq!(info["install_path"], target.to_string_lossy().as_ref());
+            assert_eq!(info["executable"], target.join("bin").join("game.exe").to_string_lossy().as_ref());
+        }
+    }
+
+    #[test]
+    fn moving_within_a_filesystem_renames_the_game_and_its_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_in(dir.path());
+        let target_root = dir.path().join("new");
+        fs::create_dir(&target_root).unwrap();
+
+        let moved = move_game(&game_dir, &target_root, &ExtractProgress::default()).unwrap();
+
+        assert_eq!(moved.game_dir, target_root.join("Game"));
+        assert!(moved.warning.is_none());
+        assert_moved(&game_dir, &moved.game_dir);
+    }
+
+    #[test]
+    fn copying_across_verifies_and_swaps_the_game_and_its_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_in(dir.path());
+        let target = dir.path().join("new").join("Game");
+        fs::create_dir(target.parent().unwrap()).unwrap();
+        let progress = ExtractProgress::default();
+
+        copy_across(&game_dir, &target, &progress).unwrap();
+        rewrite_game_info(&target, &game_dir, &target).unwrap();
+        for (_, version_dir) in versions::list(&target) {
+            rewrite_game_info(&version_dir, &game_dir, &target).unwrap();
+        }
+
+        assert_moved(&game_dir, &target);
+        let total = progress.total_bytes.load(Ordering::Relaxed);
+        assert!(total > 0);
+        assert_eq!(progress.extracted_bytes.load(Ordering::Relaxed), total);
+        assert!(!staging::staging_dir(&target).exists());
+        assert!(!staging::backup_dir(&game_dir).exists());
+    }
+
+    #[test]
+    fn a_game_info_that_cannot_be_rewritten_is_a_warning_after_the_move() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_in(dir.path());
+        fs::write(game_dir.join("vapr_game_info.json"), "not json").unwrap();
+        let target_root = dir.path().join("new");
+        fs::create_dir(&target_root).unwrap();
+
+        let moved = move_game(&game_dir, &target_root, &ExtractProgress::default()).unwrap();
+
+        assert_eq!(moved.game_dir, target_root.join("Game"));
+        assert!(moved.warning.is_some());
+        assert!(moved.game_dir.join("bin").join("game.exe").exists());
+        assert!(!game_dir.exists());
+    }
+
+    #[test]
+    fn moving_onto_an_existing_game_fails_without_touching_either() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_in(dir.path());
+        let target_root = dir.path().join("new");
+        fs::create_dir_all(target_root.join("Game")).unwrap();
+
+        assert!(move_game(&game_dir, &target_root, &ExtractProgress::default()).is_err());
+        assert!(game_dir.join("bin").join("game.exe").exists());
+        assert_eq!(fs::read_dir(target_root.join("Game")).unwrap().count(), 0);
+    }
+}
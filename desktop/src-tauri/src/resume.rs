@@ -0,0 +1,111 @@
+use reqwest::header::{HeaderMap, CONTENT_RANGE, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Identifies the exact upstream file a partial download came from, so a resume can ask
+// the server (via If-Range) to only send the remaining bytes if nothing changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResumeValidator {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl ResumeValidator {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    // Value for an If-Range header. Weak ETags are not allowed there, so those fall
+    // back to Last-Modified.
+    pub fn if_range(&self) -> Option<String> {
+        self.etag
+            .as_ref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_ref())
+            .cloned()
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize resume validator: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to save resume validator: {}", e))
+    }
+}
+
+// Sidecar file holding the ResumeValidator for a single-stream .download file
+pub fn validator_path(temp_file_path: &Path) -> PathBuf {
+    temp_file_path.with_extension("validator")
+}
+
+// Parse `Content-Range: bytes <start>-<end>/<total>` (total may be `*`)
+pub fn parse_content_range(headers: &HeaderMap) -> Option<(u64, u64, Option<u64>)> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, total) = range.split_once('/')?;
+    let (start, end) = span.split_once('-')?;
+
+    Some((
+        start.trim().parse().ok()?,
+        end.trim().parse().ok()?,
+        total.trim().parse().ok(),
+    ))
+}
+
+// The partial data on disk no longer matches what the server is serving (the file
+// changed upstream, or the server refused the range), so the download has to restart.
+#[derive(Debug)]
+pub struct ResumeInvalidated(pub String);
+
+impl fmt::Display for ResumeInvalidated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot resume download: {}", self.0)
+    }
+}
+
+impl std::error::Error for ResumeInvalidated {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_range(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_RANGE, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn parses_content_range() {
+        assert_eq!(parse_content_range(&content_range("bytes 100-199/1000")), Some((100, 199, Some(1000))));
+    }
+
+    #[test]
+    fn unknown_total_is_none() {
+        assert_eq!(parse_content_range(&content_range("bytes 0-9/*")), Some((0, 9, None)));
+    }
+
+    #[test]
+    fn malformed_content_range_is_rejected() {
+        assert_eq!(parse_content_range(&HeaderMap::new()), None);
+        assert_eq!(parse_content_range(&content_range("bytes */1000")), None);
+        assert_eq!(parse_content_range(&content_range("items 0-9/10")), None);
+        assert_eq!(parse_content_range(&content_range("bytes 0-9")), None);
+    }
+}
@@ -0,0 +1,190 @@
+use rand::Rng;
+use std::time::Duration;
+
+use crate::checksum::ChecksumMismatch;
+use crate::diskspace::InsufficientSpace;
+use crate::extract::UnsupportedArchive;
+use crate::manifest::VerificationFailed;
+use crate::resume::ResumeInvalidated;
+use crate::settings::DownloadSettings;
+
+// How often and how patiently a failed download is retried before giving up
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_settings(settings: &DownloadSettings) -> Self {
+        Self {
+            max_attempts: settings.retry_attempts,
+            base_delay: Duration::from_millis(settings.retry_base_delay_ms),
+            max_delay: Duration::from_millis(settings.retry_max_delay_ms.max(settings.retry_base_delay_ms)),
+        }
+    }
+
+    // Exponential backoff with jitter: the delay for `attempt` (1-based) is drawn from
+    // the upper half of base * 2^(attempt - 1), capped at max_delay, so clients that
+    // failed together do not all reconnect at the same instant.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_delay);
+
+        let ceiling_ms = ceiling.as_millis() as u64;
+        let jittered = rand::thread_rng().gen_range(ceiling_ms / 2..=ceiling_ms);
+        Duration::from_millis(jittered)
+    }
+}
+
+// Broad category of a download failure, reported to the frontend with download-error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // Connection dropped, timed out, DNS failure... worth retrying
+    Network,
+    // The server answered with a 5xx or 429
+    ServerUnavailable,
+    // Any other HTTP error status (404, 403...), retrying will not help
+    Http,
+    // Reading or writing local files failed
+    Storage,
+    // The target volume is too full for the download or its extracted files
+    InsufficientSpace,
+    // The downloaded data did not match what was expected
+    Integrity,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Network => "network",
+            ErrorKind::ServerUnavailable => "server-unavailable",
+            ErrorKind::Http => "http",
+            ErrorKind::Storage => "storage",
+            ErrorKind::InsufficientSpace => "insufficient-space",
+            ErrorKind::Integrity => "integrity",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ErrorKind::Network | ErrorKind::ServerUnavailable)
+    }
+}
+
+pub fn classify(error: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+        return match e.status() {
+            Some(status)
+                if status.is_server_error()
+                    || status == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+            {
+                ErrorKind::ServerUnavailable
+            }
+            Some(_) => ErrorKind::Http,
+            None if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() => {
+                ErrorKind::Network
+            }
+            None => ErrorKind::Other,
+        };
+    }
+
+    if error.is::<InsufficientSpace>() {
+        return ErrorKind::InsufficientSpace;
+    }
+
+    if let Some(e) = error.downcast_ref::<std::io::Error>() {
+        // Streams that die mid-body sometimes surface as plain I/O errors
+        return match e.kind() {
+            std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::UnexpectedEof
+            | std::io::ErrorKind::TimedOut => ErrorKind::Network,
+            _ => ErrorKind::Storage,
+        };
+    }
+
+    if error.is::<ResumeInvalidated>()
+        || error.is::<ChecksumMismatch>()
+        || error.is::<UnsupportedArchive>()
+        || error.is::<VerificationFailed>()
+    {
+        return ErrorKind::Integrity;
+    }
+
+    ErrorKind::Other
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(base_ms: u64, max_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(base_ms),
+            max_delay: Duration::from_millis(max_ms),
+        }
+    }
+
+    // Jitter is random, so check the bounds over many draws
+    fn assert_delays_within(policy: &RetryPolicy, attempt: u32, low_ms: u64, high_ms: u64) {
+        for _ in 0..1000 {
+            let delay = policy.delay_for(attempt).as_millis() as u64;
+            assert!((low_ms..=high_ms).contains(&delay), "attempt {}: {}ms", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn delays_double_with_each_attempt() {
+        let policy = policy(1000, 60_000);
+        assert_delays_within(&policy, 1, 500, 1000);
+        assert_delays_within(&policy, 2, 1000, 2000);
+        assert_delays_within(&policy, 3, 2000, 4000);
+        assert_delays_within(&policy, 5, 8000, 16_000);
+    }
+
+    #[test]
+    fn delays_are_capped_at_the_maximum() {
+        let policy = policy(1000, 5000);
+        assert_delays_within(&policy, 4, 2500, 5000);
+        assert_delays_within(&policy, 30, 2500, 5000);
+        assert_delays_within(&policy, u32::MAX, 2500, 5000);
+    }
+
+    #[test]
+    fn attempt_zero_is_treated_as_the_first() {
+        assert_delays_within(&policy(1000, 60_000), 0, 500, 1000);
+    }
+
+    #[test]
+    fn settings_never_cap_below_the_base_delay() {
+        let settings = DownloadSettings {
+            retry_base_delay_ms: 2000,
+            retry_max_delay_ms: 500,
+            ..DownloadSettings::default()
+        };
+        let policy = RetryPolicy::from_settings(&settings);
+        assert_eq!(policy.max_delay, Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn only_network_and_server_failures_are_retried() {
+        let reset = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(classify(&reset), ErrorKind::Network);
+        assert!(classify(&reset).is_transient());
+
+        let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert_eq!(classify(&denied), ErrorKind::Storage);
+        assert!(!classify(&denied).is_transient());
+
+        let full = InsufficientSpace { required: 2, available: 1 };
+        assert_eq!(classify(&full), ErrorKind::InsufficientSpace);
+        assert_eq!(classify(&VerificationFailed("bad".to_string())), ErrorKind::Integrity);
+    }
+}
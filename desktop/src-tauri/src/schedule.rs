@@ -0,0 +1,141 @@
+use chrono::{DateTime, Duration, Local, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+// Local hours a download may run in, e.g. 1 -> 7 for an off-peak plan. The window may wrap
+// past midnight (23 -> 7); equal hours mean the whole day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HourWindow {
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+impl HourWindow {
+    fn contains(&self, hour: u32) -> bool {
+        if self.start_hour == self.end_hour {
+            true
+        } else if self.start_hour < self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+// When a download is allowed to run. With neither part set it runs as soon as it reaches
+// the front of the queue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadSchedule {
+    #[serde(default)]
+    pub start_after: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub window: Option<HourWindow>,
+}
+
+impl DownloadSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(window) = self.window {
+            if window.start_hour > 23 || window.end_hour > 23 {
+                return Err("Schedule hours must be between 0 and 23".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_open(&self, now: DateTime<Local>) -> bool {
+        self.start_after.is_none_or(|start_after| now >= start_after)
+            && self.window.is_none_or(|window| window.contains(now.hour()))
+    }
+
+    // When the download may next run, or None if it may run right now
+    pub fn next_start(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.is_open(now) {
+            return None;
+        }
+
+        let mut candidate = match self.start_after {
+            Some(start_after) if start_after > now => start_after.with_timezone(&Local),
+            _ => now,
+        };
+
+        if let Some(window) = self.window {
+            if !window.contains(candidate.hour()) {
+                // Step to the top of each following hour until the window opens
+                candidate = candidate
+                    .with_minute(0)
+                    .and_then(|t| t.with_second(0))
+                    .and_then(|t| t.with_nanosecond(0))
+                    .unwrap_or(candidate);
+                for _ in 0..24 {
+                    candidate += Duration::hours(1);
+                    if window.contains(candidate.hour()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Some(candidate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 6, day, hour, minute, 0).unwrap()
+    }
+
+    fn window(start_hour: u32, end_hour: u32) -> Option<HourWindow> {
+        Some(HourWindow { start_hour, end_hour })
+    }
+
+    #[test]
+    fn open_schedules_have_no_next_start() {
+        let schedule = DownloadSchedule {
+            start_after: None,
+            window: window(1, 7),
+        };
+        assert_eq!(schedule.next_start(local(10, 3, 15)), None);
+    }
+
+    #[test]
+    fn waits_for_start_after() {
+        let start_after = local(11, 9, 30);
+        let schedule = DownloadSchedule {
+            start_after: Some(start_after.with_timezone(&Utc)),
+            window: None,
+        };
+        assert_eq!(schedule.next_start(local(10, 12, 0)), Some(start_after));
+    }
+
+    #[test]
+    fn waits_for_the_window_to_open() {
+        let schedule = DownloadSchedule {
+            start_after: None,
+            window: window(1, 7),
+        };
+        assert_eq!(schedule.next_start(local(10, 12, 45)), Some(local(11, 1, 0)));
+    }
+
+    #[test]
+    fn windows_wrap_past_midnight() {
+        let schedule = DownloadSchedule {
+            start_after: None,
+            window: window(23, 2),
+        };
+        assert_eq!(schedule.next_start(local(10, 22, 10)), Some(local(10, 23, 0)));
+        assert_eq!(schedule.next_start(local(10, 23, 10)), None);
+        assert_eq!(schedule.next_start(local(11, 1, 59)), None);
+    }
+
+    #[test]
+    fn start_after_then_window() {
+        let schedule = DownloadSchedule {
+            start_after: Some(local(10, 8, 30).with_timezone(&Utc)),
+            window: window(20, 22),
+        };
+        assert_eq!(schedule.next_start(local(10, 6, 0)), Some(local(10, 20, 0)));
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{HashMap, HashSet};
+
+// Decides which downloads may run. Every known download keeps a slot in `order`
+// (highest priority first); only the ones in `waiting` are eligible to be started,
+// and at most `max_concurrent` of them are running at any time.
+pub struct DownloadScheduler {
+    max_concurrent: usize,
+    order: Vec<String>,
+    waiting: HashSet<String>,
+    running: HashSet<String>,
+    // Last queue position reported to the frontend for each waiting download
+    reported_positions: HashMap<String, usize>,
+}
+
+impl DownloadScheduler {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            max_concurrent: max_concurrent.max(1),
+            order: Vec::new(),
+            waiting: HashSet::new(),
+            running: HashSet::new(),
+            reported_positions: HashMap::new(),
+        }
+    }
+
+    pub fn set_max_concurrent(&mut self, max_concurrent: usize) {
+        self.max_concurrent = max_concurrent.max(1);
+    }
+
+    // Register a download without asking for it to run (e.g. restored in a paused state)
+    pub fn track(&mut self, id: &str) {
+        if !self.order.iter().any(|existing| existing == id) {
+            self.order.push(id.to_string());
+        }
+    }
+
+    // Ask for a download to run as soon as a slot is free
+    pub fn enqueue(&mut self, id: &str) {
+        self.track(id);
+        self.waiting.insert(id.to_string());
+    }
+
+    // Take a download out of the waiting queue (pause). A running task keeps its slot
+    // until it reports back through `finish`.
+    pub fn park(&mut self, id: &str) {
+        self.waiting.remove(id);
+    }
+
+    // Called when a download task exits, whatever the outcome
+    pub fn finish(&mut self, id: &str) {
+        self.running.remove(id);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.order.retain(|existing| existing != id);
+        self.waiting.remove(id);
+        self.running.remove(id);
+        self.reported_positions.remove(id);
+    }
+
+    // Move a download to `index` in the priority order (0 = highest priority)
+    pub fn move_to(&mut self, id: &str, index: usize) -> Result<(), String> {
+        let current = self
+            .order
+            .iter()
+            .position(|existing| existing == id)
+            .ok_or_else(|| "Download not found".to_string())?;
+
+        let entry = self.order.remove(current);
+        let index = index.min(self.order.len());
+        self.order.insert(index, entry);
+        Ok(())
+    }
+
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+
+    pub fn is_running(&self, id: &str) -> bool {
+        self.running.contains(id)
+    }
+
+    // 1-based position among downloads still waiting for a slot
+    pub fn queue_position(&self, id: &str) -> Option<usize> {
+        self.waiting_in_order()
+            .position(|existing| existing == id)
+            .map(|index| index + 1)
+    }
+
+    // Claim free slots for the highest-priority waiting downloads and return their ids
+    pub fn take_ready(&mut self) -> Vec<String> {
+        let free_slots = self.max_concurrent.saturating_sub(self.running.len());
+        let ready: Vec<String> = self
+            .waiting_in_order()
+            .filter(|id| !self.running.contains(*id))
+            .take(free_slots)
+            .cloned()
+            .collect();
+
+        for id in &ready {
+            self.waiting.remove(id);
+            self.running.insert(id.clone());
+            self.reported_positions.remove(id);
+        }
+
+        ready
+    }
+
+    // Queue positions that changed since the last call, so only those get re-emitted
+    pub fn position_changes(&mut self) -> Vec<(String, usize)> {
+        let positions: Vec<(String, usize)> = self
+            .waiting_in_order()
+            .enumerate()
+            .map(|(index, id)| (id.clone(), index + 1))
+            .collect();
+
+        self.reported_positions.retain(|id, _| self.waiting.contains(id));
+
+        let mut changes = Vec::new();
+        for (id, position) in positions {
+            if self.reported_positions.get(&id) != Some(&position) {
+                self.reported_positions.insert(id.clone(), position);
+                changes.push((id, position));
+            }
+        }
+        changes
+    }
+
+    fn waiting_in_order(&self) -> impl Iterator<Item = &String> {
+        self.order.iter().filter(|id| self.waiting.contains(*id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler_with(max_concurrent: usize, ids: &[&str]) -> DownloadScheduler {
+        let mut scheduler = DownloadScheduler::new(max_concurrent);
+        for id in ids {
+            scheduler.enqueue(id);
+        }
+        scheduler
+    }
+
+    #[test]
+    fn take_ready_fills_free_slots_in_priority_order() {
+        let mut scheduler = scheduler_with(2, &["a", "b", "c"]);
+
+        assert_eq!(scheduler.take_ready(), vec!["a", "b"]);
+        assert!(scheduler.take_ready().is_empty());
+
+        scheduler.finish("a");
+        assert_eq!(scheduler.take_ready(), vec!["c"]);
+    }
+
+    #[test]
+    fn take_ready_follows_reordering_and_skips_parked() {
+        let mut scheduler = scheduler_with(1, &["a", "b", "c"]);
+        scheduler.move_to("c", 0).unwrap();
+        scheduler.park("c");
+
+        assert_eq!(scheduler.take_ready(), vec!["a"]);
+        assert_eq!(scheduler.queue_position("b"), Some(1));
+        assert_eq!(scheduler.queue_position("c"), None);
+    }
+
+    #[test]
+    fn position_changes_reports_only_what_moved() {
+        let mut scheduler = scheduler_with(1, &["a", "b", "c"]);
+
+        assert_eq!(
+            scheduler.position_changes(),
+            vec![("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3)]
+        );
+        assert!(scheduler.position_changes().is_empty());
+
+        scheduler.take_ready();
+        assert_eq!(scheduler.position_changes(), vec![("b".to_string(), 1), ("c".to_string(), 2)]);
+
+        scheduler.move_to("c", 0).unwrap();
+        assert_eq!(scheduler.position_changes(), vec![("c".to_string(), 1), ("b".to_string(), 2)]);
+    }
+
+    #[test]
+    fn position_changes_reports_requeued_downloads_again() {
+        let mut scheduler = scheduler_with(1, &["a", "b"]);
+        scheduler.take_ready();
+        scheduler.position_changes();
+
+        scheduler.park("b");
+        assert!(scheduler.position_changes().is_empty());
+        scheduler.enqueue("b");
+        assert_eq!(scheduler.position_changes(), vec![("b".to_string(), 1)]);
+    }
+}
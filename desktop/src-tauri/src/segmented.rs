@@ -0,0 +1,219 @@
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::interrupt::DownloadInterrupted;
+use crate::resume::ResumeValidator;
+use crate::source::DownloadSource;
+use crate::throttle::Throttle;
+
+// Files smaller than this are not worth splitting across connections
+pub const MIN_SEGMENTED_SIZE: u64 = 32 * 1024 * 1024;
+
+// One byte range of the archive; `end` is inclusive, `done` counts bytes already written
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: u64,
+    pub end: u64,
+    pub done: u64,
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn is_complete(&self) -> bool {
+        self.done >= self.len()
+    }
+}
+
+// How a segmented download is split up. Saved next to the .download file so a paused
+// or interrupted download can resume each range where it stopped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentPlan {
+    pub total_size: u64,
+    pub segments: Vec<Segment>,
+    #[serde(default)]
+    pub validator: ResumeValidator,
+}
+
+impl SegmentPlan {
+    pub fn new(total_size: u64, connections: usize, validator: ResumeValidator) -> Self {
+        let connections = (connections.max(1) as u64).min(total_size.max(1));
+        let segment_size = total_size.div_ceil(connections);
+
+        let segments = (0..connections)
+            .map(|i| i * segment_size)
+            .take_while(|start| *start < total_size)
+            .map(|start| Segment {
+                start,
+                end: (start + segment_size).min(total_size) - 1,
+                done: 0,
+            })
+            .collect();
+
+        Self {
+            total_size,
+            segments,
+            validator,
+        }
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize segment plan: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to save segment plan: {}", e))
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.segments.iter().map(|s| s.done.min(s.len())).sum()
+    }
+}
+
+// Sidecar file holding the SegmentPlan for a given .download file
+pub fn plan_path(temp_file_path: &Path) -> PathBuf {
+    temp_file_path.with_extension("segments")
+}
+
+// Fetch every unfinished segment of `plan` in parallel, writing each range at its own
+// offset in the preallocated temp file. `downloaded` is bumped as bytes land so the
+// caller can report progress while this runs.
+pub async fn fetch_segments(
+    source: &dyn DownloadSource,
+    temp_file_path: &Path,
+    plan: Arc<Mutex<SegmentPlan>>,
+    downloaded: Arc<AtomicU64>,
+    is_paused: Arc<AtomicBool>,
+    throttle: &Throttle,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pending: Vec<usize> = {
+        let plan = plan.lock().await;
+        plan.segments
+            .iter()
+            .enumerate()
+            .filter(|(_, segment)| !segment.is_complete())
+            .map(|(index, _)| index)
+            .collect()
+    };
+
+    let workers = pending.into_iter().map(|index| {
+        fetch_segment(
+            source,
+            temp_file_path,
+            plan.clone(),
+            index,
+            downloaded.clone(),
+            is_paused.clone(),
+            throttle,
+        )
+    });
+
+    futures_util::future::try_join_all(workers).await?;
+    Ok(())
+}
+
+async fn fetch_segment(
+    source: &dyn DownloadSource,
+    temp_file_path: &Path,
+    plan: Arc<Mutex<SegmentPlan>>,
+    index: usize,
+    downloaded: Arc<AtomicU64>,
+    is_paused: Arc<AtomicBool>,
+    throttle: &Throttle,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (segment, if_range) = {
+        let plan = plan.lock().await;
+        (plan.segments[index].clone(), plan.validator.if_range())
+    };
+    let offset = segment.start + segment.done;
+
+    // The source refuses with ResumeInvalidated if the file changed since the plan was made
+    let mut stream = source
+        .fetch_range(offset, segment.end, if_range.as_deref())
+        .await?;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(temp_file_path)
+        .await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut remaining = segment.end + 1 - offset;
+
+    while let Some(chunk) = stream.next().await {
+        if is_paused.load(Ordering::Relaxed) {
+            file.flush().await?;
+            return Err(Box::new(DownloadInterrupted::Paused));
+        }
+
+        let chunk = chunk?;
+        // Never write past the end of this segment, even if the server sends extra
+        let take = (chunk.len() as u64).min(remaining) as usize;
+        file.write_all(&chunk[..take]).await?;
+        remaining -= take as u64;
+
+        plan.lock().await.segments[index].done += take as u64;
+        downloaded.fetch_add(take as u64, Ordering::Relaxed);
+        throttle.consume(take as u64).await;
+
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    file.flush().await?;
+
+    if remaining > 0 {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("Segment {} ended {} bytes early", index, remaining),
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(plan: &SegmentPlan) -> Vec<(u64, u64)> {
+        plan.segments.iter().map(|segment| (segment.start, segment.end)).collect()
+    }
+
+    #[test]
+    fn splits_into_contiguous_segments() {
+        let plan = SegmentPlan::new(10, 3, ResumeValidator::default());
+        assert_eq!(ranges(&plan), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(plan.downloaded(), 0);
+    }
+
+    #[test]
+    fn never_makes_more_segments_than_bytes() {
+        let plan = SegmentPlan::new(2, 8, ResumeValidator::default());
+        assert_eq!(ranges(&plan), vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn zero_connections_means_one() {
+        let plan = SegmentPlan::new(100, 0, ResumeValidator::default());
+        assert_eq!(ranges(&plan), vec![(0, 99)]);
+    }
+
+    #[test]
+    fn empty_files_have_no_segments() {
+        assert!(SegmentPlan::new(0, 4, ResumeValidator::default()).segments.is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+// User-tunable download behaviour, stored in <data_local_dir>/VAPR/settings.json.
+// Missing fields fall back to their defaults so older files keep loading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    pub max_concurrent_downloads: usize,
+    // Parallel connections per download when the server supports range requests
+    pub download_connections: usize,
+    // Automatic retries for transient network failures before a download errors out
+    pub retry_attempts: u32,
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    // Combined bandwidth cap for all downloads in bytes/sec, 0 = unlimited
+    pub global_rate_limit: u64,
+    // Local directory serving mirror:// download URLs, e.g. a LAN share or USB stick
+    pub mirror_directory: Option<PathBuf>,
+    // Install downloads whose URLs are all file:// or mirror:// without a signed release
+    // manifest. Their archives are then only checked against the hash sent with the
+    // download, if any, so this is off unless the user opts in.
+    pub allow_unsigned_local_installs: bool,
+    // Proxy for all outgoing requests: http://, https:// or socks5:// with optional credentials
+    pub proxy_url: Option<String>,
+    // PEM file of extra root certificates to trust, e.g. a corporate CA
+    pub ca_bundle_path: Option<PathBuf>,
+    // Seconds to wait for a connection, and for more data once connected; 0 = no limit
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+    // Previous versions of each game kept after an update for rollback_game, 0 = none.
+    // Each can take as much disk space as the game itself.
+    pub kept_versions: usize,
+    // Extra library folders games can be installed into, besides the default one
+    pub library_roots: Vec<PathBuf>,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            max_concurrent_downloads: 2,
+            download_connections: 4,
+            retry_attempts: 5,
+            retry_base_delay_ms: 1000,
+            retry_max_delay_ms: 30_000,
+            global_rate_limit: 0,
+            mirror_directory: None,
+            allow_unsigned_local_installs: false,
+            proxy_url: None,
+            ca_bundle_path: None,
+            connect_timeout_secs: 15,
+            read_timeout_secs: 60,
+            kept_versions: 0,
+            library_roots: Vec::new(),
+        }
+    }
+}
+
+fn settings_path() -> Result<PathBuf, String> {
+    Ok(crate::get_vapr_data_directory()?.join("settings.json"))
+}
+
+pub fn load_settings() -> DownloadSettings {
+    let content = match settings_path().and_then(|path| {
+        fs::read_to_string(path).map_err(|e| e.to_string())
+    }) {
+        Ok(content) => content,
+        Err(_) => return DownloadSettings::default(),
+    };
+
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        eprintln!("Ignoring invalid settings file: {}", e);
+        DownloadSettings::default()
+    })
+}
+
+pub fn save_settings(settings: &DownloadSettings) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+    fs::write(settings_path()?, json).map_err(|e| format!("Failed to save settings: {}", e))
+}
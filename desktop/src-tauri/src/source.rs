@@ -0,0 +1,787 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::OnceCell;
+
+use crate::resume::{self, ResumeInvalidated, ResumeValidator};
+
+const FILE_CHUNK_SIZE: usize = 256 * 1024;
+
+// How long a mirror gets to answer the ranking probe before it is put last
+const MIRROR_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type SourceError = Box<dyn std::error::Error + Send + Sync>;
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, SourceError>> + Send>>;
+
+// Result of probing a source before deciding how to download
+#[derive(Clone)]
+pub struct RangeSupport {
+    pub total_size: Option<u64>,
+    pub accepts_ranges: bool,
+    pub validator: ResumeValidator,
+}
+
+// Body of a fetch that runs to the end of the file
+pub struct SourceBody {
+    // Where the stream starts in the file: the requested offset, or 0 when the source
+    // sends the whole file again
+    pub offset: u64,
+    pub total_size: u64,
+    // Identifies this version of the file for later resumes
+    pub validator: ResumeValidator,
+    pub stream: ByteStream,
+}
+
+// Where the bytes of an archive come from. The downloader only talks to this, so the
+// same pipeline works over HTTP, a local file or a mirror directory.
+#[async_trait]
+pub trait DownloadSource: Send + Sync {
+    async fn probe(&self) -> Result<RangeSupport, SourceError>;
+
+    // Stream from `offset` to the end. If the source changed since `if_range` was
+    // recorded, or can't resume at all, the body restarts from 0 instead.
+    async fn fetch_from(&self, offset: u64, if_range: Option<&str>) -> Result<SourceBody, SourceError>;
+
+    // Stream exactly `start..=end` of the version identified by `if_range`. Fails with
+    // ResumeInvalidated if the source can no longer provide it.
+    async fn fetch_range(&self, start: u64, end: u64, if_range: Option<&str>) -> Result<ByteStream, SourceError>;
+}
+
+// Whether `url` reads from this machine (file:// or mirror://) rather than the network
+pub fn is_local(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .map(|parsed| matches!(parsed.scheme(), "file" | "mirror"))
+        .unwrap_or(false)
+}
+
+// Pick the source for a download URL by its scheme: http(s)://, file:// or mirror://.
+// mirror://<host>/<path> reads <mirror_directory>/<host>/<path>.
+pub fn source_for(
+    url: &str,
+    client: &reqwest::Client,
+    mirror_directory: Option<&Path>,
+) -> Result<Box<dyn DownloadSource>, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid download URL {}: {}", url, e))?;
+
+    match parsed.scheme() {
+        "http" | "https" => Ok(Box::new(HttpSource {
+            client: client.clone(),
+            url: url.to_string(),
+        })),
+        "file" => {
+            let path = parsed
+                .to_file_path()
+                .map_err(|_| format!("Invalid file URL: {}", url))?;
+            Ok(Box::new(FileSource::new(path)))
+        }
+        "mirror" => {
+            let root = mirror_directory
+                .ok_or_else(|| "No mirror directory is configured".to_string())?;
+            Ok(Box::new(MirrorSource::new(root, &parsed)?))
+        }
+        scheme => Err(format!("Unsupported download URL scheme: {}", scheme)),
+    }
+}
+
+// One source for a download with several URLs for the same file, failing over between
+// them. A single URL gets its plain source. `hash_pinned` says the finished archive is
+// checked against a known hash, which lets a partial download continue on another mirror.
+pub fn source_for_mirrors(
+    urls: &[String],
+    client: &reqwest::Client,
+    mirror_directory: Option<&Path>,
+    hash_pinned: bool,
+) -> Result<Box<dyn DownloadSource>, String> {
+    let mut unique: Vec<&String> = Vec::new();
+    for url in urls {
+        if !unique.contains(&url) {
+            unique.push(url);
+        }
+    }
+
+    match unique.as_slice() {
+        [] => Err("No download URL given".to_string()),
+        [url] => source_for(url, client, mirror_directory),
+        _ => {
+            let mirrors = unique
+                .into_iter()
+                .map(|url| {
+                    Ok(Mirror {
+                        url: url.clone(),
+                        source: source_for(url, client, mirror_directory)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(Box::new(FailoverSource::new(mirrors, hash_pinned)))
+        }
+    }
+}
+
+pub struct HttpSource {
+    client: reqwest::Client,
+    url: String,
+}
+
+fn http_stream(response: reqwest::Response) -> ByteStream {
+    Box::pin(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(|e| Box::new(e) as SourceError)),
+    )
+}
+
+#[async_trait]
+impl DownloadSource for HttpSource {
+    async fn probe(&self) -> Result<RangeSupport, SourceError> {
+        let response = self.client.head(&self.url).send().await?.error_for_status()?;
+
+        let accepts_ranges = response
+            .headers()
+            .get(reqwest::header::ACCEPT_RANGES)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.eq_ignore_ascii_case("bytes"))
+            .unwrap_or(false);
+
+        // reqwest reports a zero body length for HEAD, so read the header directly
+        let total_size = response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+
+        Ok(RangeSupport {
+            total_size,
+            accepts_ranges,
+            validator: ResumeValidator::from_headers(response.headers()),
+        })
+    }
+
+    async fn fetch_from(&self, offset: u64, if_range: Option<&str>) -> Result<SourceBody, SourceError> {
+        // If-Range makes the server send the whole file (200) instead of a range if it
+        // changed since the partial download began
+        let mut resuming = false;
+        let mut request = self.client.get(&self.url);
+        if let (true, Some(if_range)) = (offset > 0, if_range) {
+            resuming = true;
+            request = request
+                .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+                .header(reqwest::header::IF_RANGE, if_range);
+        }
+
+        let mut response = request.send().await?;
+
+        // Our offset no longer fits the upstream file; fetch it from the start
+        if resuming && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            resuming = false;
+            response = self.client.get(&self.url).send().await?;
+        }
+
+        let response = response.error_for_status()?;
+        let validator = ResumeValidator::from_headers(response.headers());
+
+        if resuming && response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            return match resume::parse_content_range(response.headers()) {
+                Some((range_start, _, total)) if range_start == offset => {
+                    let total_size =
+                        total.unwrap_or_else(|| response.content_length().unwrap_or(0) + offset);
+                    Ok(SourceBody {
+                        offset,
+                        total_size,
+                        validator,
+                        stream: http_stream(response),
+                    })
+                }
+                _ => Err(Box::new(ResumeInvalidated(
+                    "server returned the wrong range".to_string(),
+                ))),
+            };
+        }
+
+        // Anything but a matching 206 carries the full body
+        Ok(SourceBody {
+            offset: 0,
+            total_size: response.content_length().unwrap_or(0),
+            validator,
+            stream: http_stream(response),
+        })
+    }
+
+    async fn fetch_range(&self, start: u64, end: u64, if_range: Option<&str>) -> Result<ByteStream, SourceError> {
+        let mut request = self
+            .client
+            .get(&self.url)
+            .header(reqwest::header::RANGE, format!("bytes={}-{}", start, end));
+        if let Some(if_range) = if_range {
+            request = request.header(reqwest::header::IF_RANGE, if_range);
+        }
+
+        let response = request.send().await?;
+
+        // 200 means the server sent the whole file instead (it changed upstream or ignores
+        // ranges); 416 means our range no longer fits it. Either way the plan is stale.
+        match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => {}
+            reqwest::StatusCode::OK | reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+                return Err(Box::new(ResumeInvalidated(format!(
+                    "server answered {} for bytes {}-{}",
+                    response.status(),
+                    start,
+                    end
+                ))));
+            }
+            _ => {
+                response.error_for_status_ref()?;
+            }
+        }
+
+        match resume::parse_content_range(response.headers()) {
+            Some((range_start, _, _)) if range_start == start => Ok(http_stream(response)),
+            _ => Err(Box::new(ResumeInvalidated(format!(
+                "server returned the wrong range for bytes {}-{}",
+                start, end
+            )))),
+        }
+    }
+}
+
+// An archive on a local or mounted filesystem
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    // Files have no ETag, so make a strong one from size and modification time
+    async fn validator(&self) -> Result<(u64, ResumeValidator), SourceError> {
+        let metadata = tokio::fs::metadata(&self.path).await?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_nanos())
+            .unwrap_or(0);
+
+        Ok((
+            metadata.len(),
+            ResumeValidator {
+                etag: Some(format!("\"{:x}-{:x}\"", metadata.len(), modified)),
+                last_modified: None,
+            },
+        ))
+    }
+
+    async fn open_at(&self, offset: u64, len: u64) -> Result<ByteStream, SourceError> {
+        let mut file = tokio::fs::File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        Ok(file_stream(file, len))
+    }
+}
+
+// Read `len` bytes from `file` in chunks. A file that turns out shorter simply ends the
+// stream early, which the downloader reports like a dropped connection.
+fn file_stream(file: tokio::fs::File, len: u64) -> ByteStream {
+    Box::pin(futures_util::stream::unfold((file, len), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+
+        let mut buffer = vec![0u8; (FILE_CHUNK_SIZE as u64).min(remaining) as usize];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(Bytes::from(buffer)), (file, remaining - read as u64)))
+            }
+            Err(e) => Some((Err(Box::new(e) as SourceError), (file, 0))),
+        }
+    }))
+}
+
+#[async_trait]
+impl DownloadSource for FileSource {
+    async fn probe(&self) -> Result<RangeSupport, SourceError> {
+        let (size, validator) = self.validator().await?;
+        Ok(RangeSupport {
+            total_size: Some(size),
+            accepts_ranges: true,
+            validator,
+        })
+    }
+
+    async fn fetch_from(&self, offset: u64, if_range: Option<&str>) -> Result<SourceBody, SourceError> {
+        let (size, validator) = self.validator().await?;
+
+        let unchanged = if_range.is_some() && if_range == validator.if_range().as_deref();
+        let offset = if unchanged && offset <= size { offset } else { 0 };
+
+        Ok(SourceBody {
+            offset,
+            total_size: size,
+            stream: self.open_at(offset, size - offset).await?,
+            validator,
+        })
+    }
+
+    async fn fetch_range(&self, start: u64, end: u64, if_range: Option<&str>) -> Result<ByteStream, SourceError> {
+        let (size, validator) = self.validator().await?;
+
+        if if_range.is_some() && if_range != validator.if_range().as_deref() {
+            return Err(Box::new(ResumeInvalidated(format!(
+                "{} changed since the download began",
+                self.path.display()
+            ))));
+        }
+        if end >= size {
+            return Err(Box::new(ResumeInvalidated(format!(
+                "bytes {}-{} are past the end of {}",
+                start,
+                end,
+                self.path.display()
+            ))));
+        }
+
+        self.open_at(start, end - start + 1).await
+    }
+}
+
+// A directory laid out like the download hosts, e.g. a LAN share or USB stick:
+// mirror://cdn.example.com/games/foo.zip is read from <root>/cdn.example.com/games/foo.zip
+pub struct MirrorSource {
+    file: FileSource,
+}
+
+impl MirrorSource {
+    pub fn new(root: &Path, url: &reqwest::Url) -> Result<Self, String> {
+        let mut path = root.to_path_buf();
+        let host = url.host_str().unwrap_or_default();
+        let segments = url.path_segments().into_iter().flatten();
+
+        for segment in std::iter::once(host).chain(segments).filter(|s| !s.is_empty()) {
+            let segment = percent_decode(segment);
+            // Keep lookups inside the mirror directory
+            if segment == "." || segment == ".." || segment.contains(['/', '\\']) {
+                return Err(format!("Invalid mirror URL: {}", url));
+            }
+            path.push(segment);
+        }
+
+        if path == root {
+            return Err(format!("Invalid mirror URL: {}", url));
+        }
+
+        Ok(Self {
+            file: FileSource::new(path),
+        })
+    }
+}
+
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[async_trait]
+impl DownloadSource for MirrorSource {
+    async fn probe(&self) -> Result<RangeSupport, SourceError> {
+        self.file.probe().await
+    }
+
+    async fn fetch_from(&self, offset: u64, if_range: Option<&str>) -> Result<SourceBody, SourceError> {
+        self.file.fetch_from(offset, if_range).await
+    }
+
+    async fn fetch_range(&self, start: u64, end: u64, if_range: Option<&str>) -> Result<ByteStream, SourceError> {
+        self.file.fetch_range(start, end, if_range).await
+    }
+}
+
+struct Mirror {
+    url: String,
+    source: Box<dyn DownloadSource>,
+}
+
+// Several mirrors of the same file. They are ranked by probing each (range support first,
+// then latency) and used best first; when one errors the next takes over at the same byte
+// offset, provided it is known to serve the same bytes: the archive hash will catch any
+// difference once the download is complete, or its ETag matches the one the download
+// began with. Otherwise the download starts over on the new mirror.
+pub struct FailoverSource {
+    mirrors: Vec<Mirror>,
+    hash_pinned: bool,
+    state: Arc<Mutex<FailoverState>>,
+    // The best mirror's probe, once the mirrors have been ranked
+    ranked: OnceCell<Option<RangeSupport>>,
+}
+
+struct FailoverState {
+    // Mirror indices, best first, and the position of the one in use
+    order: Vec<usize>,
+    current: usize,
+    // Mirror whose validator the caller holds; others need their own to resume
+    validator_owner: Option<usize>,
+    total_size: Option<u64>,
+}
+
+impl FailoverState {
+    // Move on from `index` unless another request already has
+    fn mark_failed(&mut self, index: usize, url: &str) {
+        if self.order[self.current] == index {
+            self.current = (self.current + 1) % self.order.len();
+            eprintln!("Switching away from mirror {}", url);
+        }
+    }
+}
+
+// A mirror holds a different file than the one being downloaded
+#[derive(Debug)]
+struct MirrorMismatch(String);
+
+impl std::fmt::Display for MirrorMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mirror {} serves a different file", self.0)
+    }
+}
+
+impl std::error::Error for MirrorMismatch {}
+
+// How a request carries on with a mirror other than the one the download began on
+enum Continuation {
+    // At the requested offset, with this If-Range value
+    Resume(Option<String>),
+    // From the first byte, since the mirror can't be shown to hold the same file
+    Restart,
+}
+
+impl FailoverSource {
+    fn new(mirrors: Vec<Mirror>, hash_pinned: bool) -> Self {
+        let order = (0..mirrors.len()).collect();
+        Self {
+            mirrors,
+            hash_pinned,
+            state: Arc::new(Mutex::new(FailoverState {
+                order,
+                current: 0,
+                validator_owner: None,
+                total_size: None,
+            })),
+            ranked: OnceCell::new(),
+        }
+    }
+
+    // Probe every mirror at once and order them, the first time any request needs it
+    async fn rank(&self) -> Option<RangeSupport> {
+        self.ranked
+            .get_or_init(|| async {
+                let probes = futures_util::future::join_all(self.mirrors.iter().map(|mirror| async {
+                    let started = Instant::now();
+                    match tokio::time::timeout(MIRROR_PROBE_TIMEOUT, mirror.source.probe()).await {
+                        Ok(Ok(support)) => Some((support, started.elapsed())),
+                        Ok(Err(e)) => {
+                            eprintln!("Mirror {} failed its probe: {}", mirror.url, e);
+                            None
+                        }
+                        Err(_) => {
+                            eprintln!("Mirror {} didn't answer its probe in time", mirror.url);
+                            None
+                        }
+                    }
+                }))
+                .await;
+
+                // Stable, so the caller's order breaks ties and unreachable mirrors stay last
+                let mut order: Vec<usize> = (0..self.mirrors.len()).collect();
+                order.sort_by_key(|&index| match &probes[index] {
+                    Some((support, latency)) => (false, !support.accepts_ranges, *latency),
+                    None => (true, true, Duration::MAX),
+                });
+
+                let best = probes[order[0]].as_ref().map(|(support, _)| support.clone());
+                let mut state = self.state.lock().unwrap();
+                state.order = order;
+                state.current = 0;
+                if let Some(best) = &best {
+                    state.validator_owner = Some(state.order[0]);
+                    state.total_size = best.total_size;
+                }
+                best
+            })
+            .await
+            .clone()
+    }
+
+    // Mirrors to try for one request: the one in use, then the rest in rank order
+    fn attempt_order(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap();
+        let (after, before) = state.order.split_at(state.current);
+        before.iter().chain(after).copied().collect()
+    }
+
+    fn mark_failed(&self, index: usize) {
+        self.state.lock().unwrap().mark_failed(index, &self.mirrors[index].url);
+    }
+
+    // How to carry on with mirror `index`. Only the mirror the caller's validator came
+    // from understands it. Any other needs a file of the same size and either the same
+    // strong ETag or a pinned archive hash, and is then asked for its own validator.
+    async fn continuation(&self, index: usize, if_range: Option<&str>) -> Result<Continuation, SourceError> {
+        let (owner, total_size) = {
+            let state = self.state.lock().unwrap();
+            (state.validator_owner, state.total_size)
+        };
+        if if_range.is_none() || owner.is_none_or(|owner| owner == index) {
+            return Ok(Continuation::Resume(if_range.map(|value| value.to_string())));
+        }
+
+        let support = self.mirrors[index].source.probe().await?;
+        if total_size.is_some() && support.total_size != total_size {
+            return Err(Box::new(MirrorMismatch(self.mirrors[index].url.clone())));
+        }
+
+        let same_etag = support
+            .validator
+            .etag
+            .as_deref()
+            .is_some_and(|etag| !etag.starts_with("W/") && Some(etag) == if_range);
+        if !self.hash_pinned && !same_etag {
+            eprintln!(
+                "Mirror {} may hold a different file and the archive has no hash, starting over",
+                self.mirrors[index].url
+            );
+            return Ok(Continuation::Restart);
+        }
+        Ok(Continuation::Resume(support.validator.if_range()))
+    }
+
+    // Fail over to the next mirror if the body breaks off part-way
+    fn watch(&self, index: usize, stream: ByteStream) -> ByteStream {
+        let state = self.state.clone();
+        let url = self.mirrors[index].url.clone();
+        Box::pin(stream.inspect(move |item| {
+            if item.is_err() {
+                state.lock().unwrap().mark_failed(index, &url);
+            }
+        }))
+    }
+}
+
+#[async_trait]
+impl DownloadSource for FailoverSource {
+    async fn probe(&self) -> Result<RangeSupport, SourceError> {
+        self.rank().await.ok_or_else(|| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "No download mirror answered",
+            )) as SourceError
+        })
+    }
+
+    async fn fetch_from(&self, offset: u64, if_range: Option<&str>) -> Result<SourceBody, SourceError> {
+        self.rank().await;
+
+        let mut last_error = None;
+        for index in self.attempt_order() {
+            let fetched = async {
+                let source = &self.mirrors[index].source;
+                match self.continuation(index, if_range).await? {
+                    Continuation::Resume(if_range) => source.fetch_from(offset, if_range.as_deref()).await,
+                    // A body from offset 0 tells the caller to discard what it has
+                    Continuation::Restart => source.fetch_from(0, None).await,
+                }
+            }
+            .await;
+
+            match fetched {
+                Ok(body) => {
+                    {
+                        let mut state = self.state.lock().unwrap();
+                        // A fresh body's validator is the one the caller will resume with
+                        if body.offset == 0 {
+                            state.validator_owner = Some(index);
+                        }
+                        state.total_size = Some(body.total_size);
+                    }
+                    return Ok(SourceBody {
+                        stream: self.watch(index, body.stream),
+                        ..body
+                    });
+                }
+                Err(e) if e.is::<ResumeInvalidated>() => return Err(e),
+                Err(e) => {
+                    eprintln!("Mirror {} failed: {}", self.mirrors[index].url, e);
+                    self.mark_failed(index);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("a failover source has mirrors"))
+    }
+
+    async fn fetch_range(&self, start: u64, end: u64, if_range: Option<&str>) -> Result<ByteStream, SourceError> {
+        self.rank().await;
+
+        let mut last_error = None;
+        for index in self.attempt_order() {
+            let fetched = async {
+                match self.continuation(index, if_range).await? {
+                    Continuation::Resume(if_range) => {
+                        self.mirrors[index].source.fetch_range(start, end, if_range.as_deref()).await
+                    }
+                    // Ranges can't start over on their own; the whole download has to
+                    Continuation::Restart => Err(Box::new(ResumeInvalidated(format!(
+                        "mirror {} may hold a different file",
+                        self.mirrors[index].url
+                    ))) as SourceError),
+                }
+            }
+            .await;
+
+            match fetched {
+                Ok(stream) => return Ok(self.watch(index, stream)),
+                Err(e) if e.is::<ResumeInvalidated>() => return Err(e),
+                Err(e) => {
+                    eprintln!("Mirror {} failed: {}", self.mirrors[index].url, e);
+                    self.mark_failed(index);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("a failover source has mirrors"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror_path(root: &Path, url: &str) -> Result<PathBuf, String> {
+        MirrorSource::new(root, &reqwest::Url::parse(url).unwrap()).map(|mirror| mirror.file.path)
+    }
+
+    #[test]
+    fn percent_decode_decodes_escapes() {
+        assert_eq!(percent_decode("Game%20Name%21"), "Game Name!");
+        assert_eq!(percent_decode("plain"), "plain");
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn mirror_urls_map_into_the_mirror_directory() {
+        let root = Path::new("/mirror");
+        assert_eq!(
+            mirror_path(root, "mirror://cdn.example.com/games/My%20Game.zip").unwrap(),
+            root.join("cdn.example.com").join("games").join("My Game.zip")
+        );
+    }
+
+    #[test]
+    fn mirror_urls_may_not_leave_the_mirror_directory() {
+        let root = Path::new("/mirror");
+        assert!(mirror_path(root, "mirror://cdn.example.com/games/..%2F..%2Fetc%2Fpasswd").is_err());
+        assert!(mirror_path(root, "mirror://cdn.example.com/a%5Cb").is_err());
+        assert!(mirror_path(root, "mirror:///").is_err());
+        // The URL parser resolves dot segments itself, so they never get past the host
+        assert_eq!(
+            mirror_path(root, "mirror://cdn.example.com/%2E%2E/%2E%2E/secret").unwrap(),
+            root.join("cdn.example.com").join("secret")
+        );
+    }
+
+    #[test]
+    fn local_urls_are_recognised() {
+        assert!(is_local("file:///games/game.zip"));
+        assert!(is_local("mirror://cdn.example.com/game.zip"));
+        assert!(!is_local("https://cdn.example.com/game.zip"));
+        assert!(!is_local("not a url"));
+    }
+
+    fn write_mirror(path: &Path, modified_secs: u64) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"0123456789").unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(modified_secs);
+        std::fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    // Two file:// mirrors of the same bytes. The first is the only one up when they are
+    // ranked, so the download begins there; then it goes away and the second, whose ETag
+    // only matches if `same_etag`, has to carry on from byte 4.
+    async fn fail_over(dir: &Path, same_etag: bool, hash_pinned: bool) -> (Box<dyn DownloadSource>, String) {
+        let (first, second) = (dir.join("a").join("game.zip"), dir.join("b").join("game.zip"));
+        let urls: Vec<String> = [&first, &second]
+            .iter()
+            .map(|path| reqwest::Url::from_file_path(path).unwrap().to_string())
+            .collect();
+
+        write_mirror(&first, 1_000_000);
+        let source = source_for_mirrors(&urls, &reqwest::Client::new(), None, hash_pinned).unwrap();
+        let validator = source.probe().await.unwrap().validator.if_range().unwrap();
+
+        write_mirror(&second, if same_etag { 1_000_000 } else { 2_000_000 });
+        std::fs::remove_file(&first).unwrap();
+        (source, validator)
+    }
+
+    async fn read_all(stream: ByteStream) -> Vec<u8> {
+        stream.map(|bytes| bytes.unwrap().to_vec()).concat().await
+    }
+
+    #[tokio::test]
+    async fn failover_resumes_on_another_mirror_when_the_archive_hash_is_known() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, validator) = fail_over(dir.path(), false, true).await;
+
+        let body = source.fetch_from(4, Some(&validator)).await.unwrap();
+        assert_eq!(body.offset, 4);
+        assert_eq!(read_all(body.stream).await, b"456789");
+    }
+
+    #[tokio::test]
+    async fn failover_resumes_on_another_mirror_with_the_same_etag() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, validator) = fail_over(dir.path(), true, false).await;
+
+        let body = source.fetch_from(4, Some(&validator)).await.unwrap();
+        assert_eq!(body.offset, 4);
+    }
+
+    #[tokio::test]
+    async fn failover_restarts_on_a_mirror_that_may_differ() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, validator) = fail_over(dir.path(), false, false).await;
+
+        let body = source.fetch_from(4, Some(&validator)).await.unwrap();
+        assert_eq!(body.offset, 0);
+        assert_eq!(read_all(body.stream).await, b"0123456789");
+
+        let ranged = source.fetch_range(4, 7, Some(&validator)).await;
+        assert!(ranged.err().unwrap().is::<ResumeInvalidated>());
+    }
+}
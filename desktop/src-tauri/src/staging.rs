@@ -0,0 +1,355 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{diskspace, versions};
+
+// A new build is put together in <game>.vapr-staging beside the live install and swapped
+// in by renaming directories, so the game is always either the old build or the new one
+const STAGING_SUFFIX: &str = ".vapr-staging";
+// Where the live install sits while the swap is in progress
+const BACKUP_SUFFIX: &str = ".vapr-old";
+
+pub struct StagedInstall {
+    game_dir: PathBuf,
+    staging_dir: PathBuf,
+}
+
+fn sibling(game_dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = game_dir.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    game_dir.with_file_name(name)
+}
+
+pub fn staging_dir(game_dir: &Path) -> PathBuf {
+    sibling(game_dir, STAGING_SUFFIX)
+}
+
+pub fn backup_dir(game_dir: &Path) -> PathBuf {
+    sibling(game_dir, BACKUP_SUFFIX)
+}
+
+// Replace the file at `path` by writing a temp file beside it and renaming it over. Files
+// in a game directory may be hard links shared with a kept version, which writing in
+// place would change too. Blocking.
+pub fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
+// Staging, backup and kept-version directories live among the games but aren't games
+// themselves
+pub fn is_internal_dir(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            name.ends_with(STAGING_SUFFIX)
+                || name.ends_with(BACKUP_SUFFIX)
+                || name.ends_with(versions::VERSIONS_SUFFIX)
+        })
+}
+
+// Tidy up after a swap the app didn't live to finish: a game whose directory is missing
+// gets its backup back, and leftover staging and backup directories are removed. Only
+// safe while no install is running.
+pub fn recover(games_dir: &Path) {
+    let Ok(entries) = fs::read_dir(games_dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if let Some(game_name) = name.strip_suffix(BACKUP_SUFFIX) {
+            let game_dir = games_dir.join(game_name);
+            if !game_dir.exists() {
+                eprintln!("Restoring {} from an interrupted update", game_name);
+                if fs::rename(&path, &game_dir).is_ok() {
+                    continue;
+                }
+            }
+            let _ = fs::remove_dir_all(&path);
+        } else if name.ends_with(STAGING_SUFFIX) {
+            let _ = fs::remove_dir_all(&path);
+        }
+    }
+}
+
+impl StagedInstall {
+    // Start an empty staging directory, for builds that bring every file with them, so
+    // nothing the old build had survives into the new one. Blocking.
+    pub fn prepare_empty(game_dir: &Path) -> io::Result<Self> {
+        let staging_dir = staging_dir(game_dir);
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        fs::create_dir_all(&staging_dir)?;
+
+        Ok(Self {
+            game_dir: game_dir.to_path_buf(),
+            staging_dir,
+        })
+    }
+
+    // Start a staging directory that mirrors the live install, except for `exclude`
+    // (top-level paths such as the downloaded archive). Files are hard links where the
+    // filesystem allows, so whatever installs into the staging directory must replace
+    // files rather than write into them. Filesystems without hard links (FAT, exFAT) get
+    // copies, which need as much free space again as the install. Blocking.
+    pub fn prepare(game_dir: &Path, exclude: &[PathBuf]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let staged = Self::prepare_empty(game_dir)?;
+        if !game_dir.exists() {
+            return Ok(staged);
+        }
+
+        let mirrored = check_copy_space(game_dir, &staged.staging_dir, exclude)
+            .and_then(|_| Ok(mirror_dir(game_dir, &staged.staging_dir, exclude)?));
+        if let Err(e) = mirrored {
+            staged.discard();
+            return Err(e);
+        }
+        Ok(staged)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.staging_dir
+    }
+
+    pub fn game_dir(&self) -> &Path {
+        &self.game_dir
+    }
+
+    // Swap the staged build in. The old install is moved aside first and only retired once
+    // the new one is in place, kept as one of the `kept_versions` previous versions; if the
+    // swap fails it is put back and the staged build is thrown away. Blocking.
+    pub fn commit(self, kept_versions: usize) -> io::Result<()> {
+        let backup_dir = backup_dir(&self.game_dir);
+        let swapped = self.swap(&backup_dir);
+        if swapped.is_err() {
+            self.discard();
+            return swapped;
+        }
+
+        versions::retire(&self.game_dir, &backup_dir, kept_versions);
+        Ok(())
+    }
+
+    fn swap(&self, backup_dir: &Path) -> io::Result<()> {
+        if backup_dir.exists() {
+            fs::remove_dir_all(backup_dir)?;
+        }
+
+        // Fails on Windows while the game is running, leaving the old build untouched
+        if self.game_dir.exists() {
+            fs::rename(&self.game_dir, backup_dir)?;
+        }
+        if let Err(e) = fs::rename(&self.staging_dir, &self.game_dir) {
+            let _ = fs::rename(backup_dir, &self.game_dir);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Throw the staged build away, leaving the live install as it was. Blocking.
+    pub fn discard(self) {
+        let _ = fs::remove_dir_all(&self.staging_dir);
+    }
+}
+
+// Mirroring copies every file when `game_dir` can't be hard-linked into `staging_dir`, so
+// make sure that copy fits before starting it
+fn check_copy_space(
+    game_dir: &Path,
+    staging_dir: &Path,
+    exclude: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(file) = first_file(game_dir, exclude)? else {
+        return Ok(());
+    };
+    let probe = staging_dir.join(".vapr-link-probe");
+    if fs::hard_link(&file, &probe).is_ok() {
+        fs::remove_file(&probe)?;
+        return Ok(());
+    }
+
+    let excluded: u64 = exclude
+        .iter()
+        .filter_map(|path| fs::symlink_metadata(path).ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+    let required = diskspace::dir_size(game_dir)?.saturating_sub(excluded);
+    diskspace::ensure_available(staging_dir, required)
+}
+
+fn first_file(dir: &Path, exclude: &[PathBuf]) -> io::Result<Option<PathBuf>> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if exclude.contains(&path) {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_file() {
+            return Ok(Some(path));
+        }
+        if file_type.is_dir() {
+            if let Some(file) = first_file(&path, &[])? {
+                return Ok(Some(file));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn mirror_dir(source: &Path, target: &Path, exclude: &[PathBuf]) -> io::Result<()> {
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        if exclude.contains(&path) {
+            continue;
+        }
+
+        let destination = target.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            fs::create_dir(&destination)?;
+            mirror_dir(&path, &destination, &[])?;
+        } else if file_type.is_symlink() {
+            mirror_symlink(&path, &destination)?;
+        } else if fs::hard_link(&path, &destination).is_err() {
+            fs::copy(&path, &destination)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mirror_symlink(path: &Path, destination: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(path)?, destination)
+}
+
+// Creating symlinks needs extra privileges on Windows, so copy what the link points at
+#[cfg(not(unix))]
+fn mirror_symlink(path: &Path, destination: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::create_dir(destination)?;
+        mirror_dir(path, destination, &[])
+    } else {
+        fs::copy(path, destination).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Files are replaced, not written into, as they may be hard links into the live install
+    fn write_game(game_dir: &Path, version: &str, files: &[(&str, &str)]) {
+        fs::create_dir_all(game_dir).unwrap();
+        let info = serde_json::json!({ "version": version, "installed_at": chrono::Utc::now().to_rfc3339() });
+        replace_file(&game_dir.join("vapr_game_info.json"), info.to_string().as_bytes()).unwrap();
+        for (path, contents) in files {
+            replace_file(&game_dir.join(path), contents.as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn prepare_mirrors_the_install_except_excluded_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("Game");
+        write_game(&game_dir, "1.0.0", &[("game.exe", "v1"), ("Game.download", "archive")]);
+        fs::create_dir(game_dir.join("data")).unwrap();
+        fs::write(game_dir.join("data").join("level.pak"), "level").unwrap();
+
+        let staged = StagedInstall::prepare(&game_dir, &[game_dir.join("Game.download")]).unwrap();
+        assert_eq!(fs::read_to_string(staged.dir().join("game.exe")).unwrap(), "v1");
+        assert_eq!(fs::read_to_string(staged.dir().join("data").join("level.pak")).unwrap(), "level");
+        assert!(!staged.dir().join("Game.download").exists());
+        assert!(!staged.dir().join(".vapr-link-probe").exists());
+
+        staged.discard();
+        assert!(!staging_dir(&game_dir).exists());
+        assert!(game_dir.join("game.exe").exists());
+    }
+
+    #[test]
+    fn prepare_empty_leaves_the_old_files_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("Game");
+        write_game(&game_dir, "1.0.0", &[("game.exe", "v1"), ("dropped.dll", "old")]);
+
+        let staged = StagedInstall::prepare_empty(&game_dir).unwrap();
+        write_game(staged.dir(), "2.0.0", &[("game.exe", "v2")]);
+        staged.commit(0).unwrap();
+
+        assert_eq!(fs::read_to_string(game_dir.join("game.exe")).unwrap(), "v2");
+        assert!(!game_dir.join("dropped.dll").exists());
+    }
+
+    #[test]
+    fn commit_swaps_the_staged_build_in_and_keeps_the_old_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("Game");
+        write_game(&game_dir, "1.0.0", &[("game.exe", "v1")]);
+
+        let staged = StagedInstall::prepare(&game_dir, &[]).unwrap();
+        write_game(staged.dir(), "2.0.0", &[("game.exe", "v2")]);
+        staged.commit(1).unwrap();
+
+        assert_eq!(fs::read_to_string(game_dir.join("game.exe")).unwrap(), "v2");
+        let kept = versions::list(&game_dir);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].0, "1.0.0");
+        assert_eq!(fs::read_to_string(kept[0].1.join("game.exe")).unwrap(), "v1");
+        assert!(!staging_dir(&game_dir).exists());
+        assert!(!backup_dir(&game_dir).exists());
+    }
+
+    #[test]
+    fn a_failed_commit_leaves_the_live_install_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("Game");
+        write_game(&game_dir, "1.0.0", &[("game.exe", "v1")]);
+
+        // With the staging directory gone the swap can't complete
+        let staged = StagedInstall::prepare(&game_dir, &[]).unwrap();
+        fs::remove_dir_all(staged.dir()).unwrap();
+        assert!(staged.commit(1).is_err());
+
+        assert_eq!(fs::read_to_string(game_dir.join("game.exe")).unwrap(), "v1");
+        assert!(versions::list(&game_dir).is_empty());
+        assert!(!backup_dir(&game_dir).exists());
+    }
+
+    #[test]
+    fn recover_restores_interrupted_swaps_and_removes_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        // Interrupted between moving the live install aside and moving the new one in
+        let moved_aside = dir.path().join("Moved");
+        write_game(&backup_dir(&moved_aside), "1.0.0", &[("game.exe", "v1")]);
+        write_game(&staging_dir(&moved_aside), "2.0.0", &[("game.exe", "v2")]);
+        // Interrupted after the swap, before the old install was retired
+        let swapped = dir.path().join("Swapped");
+        write_game(&swapped, "2.0.0", &[("game.exe", "v2")]);
+        write_game(&backup_dir(&swapped), "1.0.0", &[("game.exe", "v1")]);
+
+        recover(dir.path());
+
+        assert_eq!(fs::read_to_string(moved_aside.join("game.exe")).unwrap(), "v1");
+        assert_eq!(fs::read_to_string(swapped.join("game.exe")).unwrap(), "v2");
+        let mut left: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["Moved", "Swapped"]);
+    }
+}
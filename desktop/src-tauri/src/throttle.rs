@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+// How much unused allowance may pile up, in seconds' worth of bytes
+const BURST_SECONDS: f64 = 0.5;
+
+// Longest single sleep, so a rate change made while throttled applies quickly
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, bytes_per_sec: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        let capacity = bytes_per_sec as f64 * BURST_SECONDS;
+        self.available = (self.available + elapsed * bytes_per_sec as f64).min(capacity);
+    }
+}
+
+// Token bucket limiting throughput to `bytes_per_sec` (0 = unlimited). The rate can be
+// changed at any time and takes effect for downloads that are already running.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: AtomicU64::new(bytes_per_sec),
+            bucket: Mutex::new(Bucket {
+                available: 0.0,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.bytes_per_sec.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        self.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+    }
+
+    // Account for `bytes` just received, waiting as long as needed to stay under the rate
+    pub async fn consume(&self, bytes: u64) {
+        {
+            let rate = self.rate();
+            if rate == 0 {
+                return;
+            }
+            let mut bucket = self.bucket.lock().await;
+            bucket.refill(rate);
+            bucket.available -= bytes as f64;
+        }
+
+        loop {
+            let rate = self.rate();
+            let deficit = {
+                let mut bucket = self.bucket.lock().await;
+                if rate == 0 {
+                    // Limit lifted while we were waiting; forget the debt
+                    bucket.available = 0.0;
+                    return;
+                }
+                bucket.refill(rate);
+                -bucket.available
+            };
+
+            if deficit <= 0.0 {
+                return;
+            }
+
+            let wait = Duration::from_secs_f64(deficit / rate as f64).min(MAX_WAIT);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// The limiters a download's bytes count against: the global one and its own
+#[derive(Clone)]
+pub struct Throttle {
+    limiters: Vec<Arc<RateLimiter>>,
+}
+
+impl Throttle {
+    pub fn new(limiters: Vec<Arc<RateLimiter>>) -> Self {
+        Self { limiters }
+    }
+
+    pub async fn consume(&self, bytes: u64) {
+        for limiter in &self.limiters {
+            limiter.consume(bytes).await;
+        }
+    }
+
+    // Tightest limit currently in force, if any
+    pub fn effective_rate(&self) -> Option<u64> {
+        self.limiters
+            .iter()
+            .map(|limiter| limiter.rate())
+            .filter(|rate| *rate > 0)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Time is paused in these tests and only moves when everything is waiting, so each
+    // elapsed time is exactly what the limiter slept for
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_never_waits() {
+        let limiter = RateLimiter::new(0);
+        let started = Instant::now();
+        limiter.consume(10_000_000).await;
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn consuming_waits_until_the_rate_allows_it() {
+        let limiter = RateLimiter::new(1000);
+        let started = Instant::now();
+        limiter.consume(2000).await;
+
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(2), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(2100), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_time_builds_up_at_most_a_short_burst() {
+        let limiter = RateLimiter::new(1000);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        // Half a second's worth is free, the rest is paid for at the rate
+        let started = Instant::now();
+        limiter.consume(1500).await;
+
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1100), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lifting_the_limit_releases_a_waiting_download() {
+        let limiter = Arc::new(RateLimiter::new(100));
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.consume(100_000).await }
+        });
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        limiter.set_rate(0);
+
+        let started = Instant::now();
+        waiting.await.unwrap();
+        assert!(started.elapsed() <= MAX_WAIT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttles_are_held_to_their_tightest_limit() {
+        let throttle = Throttle::new(vec![Arc::new(RateLimiter::new(0)), Arc::new(RateLimiter::new(500))]);
+        assert_eq!(throttle.effective_rate(), Some(500));
+
+        let started = Instant::now();
+        throttle.consume(1000).await;
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        assert_eq!(Throttle::new(vec![Arc::new(RateLimiter::new(0))]).effective_rate(), None);
+    }
+}
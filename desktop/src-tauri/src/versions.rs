@@ -0,0 +1,234 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::staging;
+
+// Previous versions of a game live in <game>.vapr-versions/<version>, each a complete
+// install with its own vapr_game_info.json. After a chunked or patched update, files the
+// update didn't touch are hard links shared with the live install. A full-archive update
+// rewrites every file, so the version it retires is a full copy of the game.
+pub const VERSIONS_SUFFIX: &str = ".vapr-versions";
+
+pub fn versions_dir(game_dir: &Path) -> PathBuf {
+    let mut name = game_dir.file_name().unwrap_or_default().to_os_string();
+    name.push(VERSIONS_SUFFIX);
+    game_dir.with_file_name(name)
+}
+
+fn read_game_info(dir: &Path) -> Option<serde_json::Value> {
+    let content = fs::read_to_string(dir.join("vapr_game_info.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+// Versions come from the release manifest; keep them to one safe path component
+fn version_dir_name(version: &str) -> String {
+    version
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+') { c } else { '_' })
+        .collect()
+}
+
+// Kept versions of the game in `game_dir` as (version, directory), most recently
+// installed first
+pub fn list(game_dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(versions_dir(game_dir)) else {
+        return Vec::new();
+    };
+
+    let mut versions: Vec<(String, String, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let info = read_game_info(&path)?;
+            let version = info["version"].as_str()?.to_string();
+            let installed_at = info["installed_at"].as_str().unwrap_or_default().to_string();
+            Some((installed_at, version, path))
+        })
+        .collect();
+
+    // RFC 3339 timestamps in UTC sort chronologically as strings
+    versions.sort_by(|a, b| b.0.cmp(&a.0));
+    versions.into_iter().map(|(_, version, path)| (version, path)).collect()
+}
+
+// Keep `old_dir`, the install an update just replaced, as a previous version of the game
+// in `game_dir`, then drop the oldest beyond `keep`. Blocking.
+pub fn retire(game_dir: &Path, old_dir: &Path, keep: usize) {
+    let version = read_game_info(old_dir).and_then(|info| info["version"].as_str().map(version_dir_name));
+    match version.filter(|_| keep > 0) {
+        Some(version) => {
+            let target = versions_dir(game_dir).join(version);
+            let _ = fs::remove_dir_all(&target);
+            let kept = fs::create_dir_all(versions_dir(game_dir)).and_then(|_| fs::rename(old_dir, &target));
+            if let Err(e) = kept {
+                eprintln!("Failed to keep previous version of {}: {}", game_dir.display(), e);
+                let _ = fs::remove_dir_all(old_dir);
+            }
+        }
+        None => {
+            let _ = fs::remove_dir_all(old_dir);
+        }
+    }
+
+    // Also drops versions kept before the limit was lowered
+    prune(game_dir, keep);
+}
+
+// Delete kept versions beyond the `keep` most recently installed. Blocking.
+pub fn prune(game_dir: &Path, keep: usize) {
+    for (_, path) in list(game_dir).into_iter().skip(keep) {
+        let _ = fs::remove_dir_all(path);
+    }
+
+    // Don't leave an empty versions directory behind
+    let _ = fs::remove_dir(versions_dir(game_dir));
+}
+
+// Swap the live install in `game_dir` for a kept version (the newest unless `version` is
+// given). The install it replaces is retired like one an update replaces: kept as the
+// newest previous version when `keep` allows, so the rollback can be undone, and deleted
+// when no versions are kept. Returns the restored game's rewritten info. Blocking.
+pub fn rollback(game_dir: &Path, version: Option<&str>, keep: usize) -> Result<serde_json::Value, String> {
+    let versions = list(game_dir);
+    let (restored_version, restored_dir) = match version {
+        Some(version) => versions.into_iter().find(|(kept, _)| kept == version),
+        None => versions.into_iter().next(),
+    }
+    .ok_or_else(|| match version {
+        Some(version) => format!("Version {} of this game was not kept", version),
+        None => "No previous version of this game was kept".to_string(),
+    })?;
+
+    let current_version = read_game_info(game_dir)
+        .and_then(|info| info["version"].as_str().map(|v| v.to_string()));
+
+    // Same dance as committing a staged install, so an interrupted rollback is recovered
+    // the same way at startup
+    let backup_dir = staging::backup_dir(game_dir);
+    let _ = fs::remove_dir_all(&backup_dir);
+    fs::rename(game_dir, &backup_dir)
+        .map_err(|e| format!("Failed to move the current version aside (is the game running?): {}", e))?;
+    if let Err(e) = fs::rename(&restored_dir, game_dir) {
+        let _ = fs::rename(&backup_dir, game_dir);
+        return Err(format!("Failed to restore version {}: {}", restored_version, e));
+    }
+
+    retire(game_dir, &backup_dir, keep);
+
+    let mut info = read_game_info(game_dir)
+        .ok_or_else(|| format!("Version {} has no game info", restored_version))?;
+    info["rolled_back_from"] = serde_json::json!(current_version);
+    info["rolled_back_at"] = serde_json::json!(chrono::Utc::now().to_rfc3339());
+
+    let info_path = game_dir.join("vapr_game_info.json");
+    staging::replace_file(&info_path, serde_json::to_string_pretty(&info).unwrap().as_bytes())
+        .map_err(|e| format!("Failed to update game info: {}", e))?;
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An install of `version`, installed on day `day` of 2024
+    fn write_install(dir: &Path, version: &str, day: u32) {
+        fs::create_dir_all(dir).unwrap();
+        let info = serde_json::json!({
+            "version": version,
+            "installed_at": format!("2024-01-{:02}T00:00:00+00:00", day)
+        });
+        fs::write(dir.join("vapr_game_info.json"), info.to_string()).unwrap();
+        fs::write(dir.join("game.exe"), version).unwrap();
+    }
+
+    fn kept(game_dir: &Path) -> Vec<String> {
+        list(game_dir).into_iter().map(|(version, _)| version).collect()
+    }
+
+    fn live_version(game_dir: &Path) -> String {
+        fs::read_to_string(game_dir.join("game.exe")).unwrap()
+    }
+
+    // A game at 3.0.0 that kept 1.0.0 and 2.0.0
+    fn game_with_history(dir: &Path) -> PathBuf {
+        let game_dir = dir.join("Game");
+        write_install(&versions_dir(&game_dir).join("1.0.0"), "1.0.0", 1);
+        write_install(&versions_dir(&game_dir).join("2.0.0"), "2.0.0", 2);
+        write_install(&game_dir, "3.0.0", 3);
+        game_dir
+    }
+
+    #[test]
+    fn retire_keeps_the_newest_versions_up_to_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_with_history(dir.path());
+        let old_dir = dir.path().join("old");
+        write_install(&old_dir, "2.5.0", 4);
+
+        retire(&game_dir, &old_dir, 2);
+
+        assert_eq!(kept(&game_dir), ["2.5.0", "2.0.0"]);
+        assert!(!old_dir.exists());
+    }
+
+    #[test]
+    fn retire_deletes_everything_when_no_versions_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_with_history(dir.path());
+        let old_dir = dir.path().join("old");
+        write_install(&old_dir, "2.5.0", 4);
+
+        retire(&game_dir, &old_dir, 0);
+
+        assert!(kept(&game_dir).is_empty());
+        assert!(!versions_dir(&game_dir).exists());
+        assert!(!old_dir.exists());
+    }
+
+    #[test]
+    fn rollback_restores_the_newest_kept_version_and_keeps_the_current_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_with_history(dir.path());
+
+        let info = rollback(&game_dir, None, 2).unwrap();
+
+        assert_eq!(live_version(&game_dir), "2.0.0");
+        assert_eq!(info["version"], "2.0.0");
+        assert_eq!(info["rolled_back_from"], "3.0.0");
+        assert_eq!(kept(&game_dir), ["3.0.0", "1.0.0"]);
+        assert!(!staging::backup_dir(&game_dir).exists());
+    }
+
+    #[test]
+    fn rollback_to_a_given_version_prunes_to_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_with_history(dir.path());
+
+        rollback(&game_dir, Some("1.0.0"), 1).unwrap();
+
+        assert_eq!(live_version(&game_dir), "1.0.0");
+        assert_eq!(kept(&game_dir), ["3.0.0"]);
+    }
+
+    #[test]
+    fn rollback_without_kept_versions_discards_the_version_it_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_with_history(dir.path());
+
+        rollback(&game_dir, Some("2.0.0"), 0).unwrap();
+
+        assert_eq!(live_version(&game_dir), "2.0.0");
+        assert!(kept(&game_dir).is_empty());
+    }
+
+    #[test]
+    fn rollback_to_a_version_that_was_not_kept_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = game_with_history(dir.path());
+
+        assert!(rollback(&game_dir, Some("0.9.0"), 2).is_err());
+        assert_eq!(live_version(&game_dir), "3.0.0");
+        assert_eq!(kept(&game_dir), ["2.0.0", "1.0.0"]);
+    }
+}